@@ -1,14 +1,27 @@
-use std::f32::consts::PI;
-
+use crate::parameter::Smoother;
+use crate::processor::{Chain, Processor};
+use crate::transfer_curve::TransferCurve;
 use crate::units::*;
 
+//Release times above this select the program-dependent release
+pub const AUTO_RELEASE: f32 = 1000.0;
+//Time constants of the auto release stages, in ms
+const AUTO_FAST_RELEASE: f32 = 50.0;
+const AUTO_SLOW_ATTACK: f32 = 500.0;
+const AUTO_SLOW_RELEASE: f32 = 1500.0;
+
+//dB below the noise floor over which the upward boost fades out
+const NOISE_FLOOR_FADE: f64 = 6.0;
+
 //x, threshold, & width units are dB
 //slope is: 1.0 / ratio - 1.0 (Computed ahead of time for performance)
-fn reiss(x: f32, threshold: f32, width: f32, ratio: f32, slope: f32) -> f32 {
+fn reiss<T: Float>(x: T, threshold: T, width: T, ratio: T, slope: T) -> T {
+    let two = T::from_f64(2.0);
     let x_minus_threshold = x - threshold;
-    if 2.0 * (x_minus_threshold).abs() <= width {
-        x + slope * (x_minus_threshold + width / 2.0).powi(2) / (2.0 * width)
-    } else if 2.0 * (x_minus_threshold) > width {
+    //A zero width knee is hard, the knee's formula would divide by zero at threshold
+    if two * (x_minus_threshold).abs() <= width && width > T::default() {
+        x + slope * (x_minus_threshold + width / two).powi(2) / (two * width)
+    } else if two * (x_minus_threshold) > width {
         threshold + (x_minus_threshold) / ratio
     } else {
         // if 2.0 * (x_minus_threshold) < -width
@@ -16,138 +29,995 @@ fn reiss(x: f32, threshold: f32, width: f32, ratio: f32, slope: f32) -> f32 {
     }
 }
 
-pub struct DecoupledPeakDetector {
-    attack: f32,
-    release: f32,
-    env: f32,
-    env2: f32,
+//Downward expander, below threshold the level falls ratio times faster than the input
+//x, threshold, & width units are dB
+fn reiss_expander<T: Float>(x: T, threshold: T, width: T, ratio: T) -> T {
+    let two = T::from_f64(2.0);
+    let x_minus_threshold = x - threshold;
+    //Hard at zero width, like reiss
+    if two * (x_minus_threshold).abs() <= width && width > T::default() {
+        x - (ratio - T::from_f64(1.0)) * (x_minus_threshold - width / two).powi(2) / (two * width)
+    } else if two * (x_minus_threshold) < -width {
+        threshold + (x_minus_threshold) * ratio
+    } else {
+        // if 2.0 * (x_minus_threshold) > width
+        x
+    }
+}
+
+//What the gain computer does with the level relative to threshold
+#[derive(Clone, Copy, PartialEq)]
+pub enum Mode {
+    //Downward compression above threshold
+    Compress,
+    //Downward expansion below threshold
+    Expand,
+    //Full range attenuation below threshold
+    Gate,
+    //Raises material below threshold
+    Upward,
+    //Follows a user drawn TransferCurve instead of threshold, knee & ratio
+    Custom,
+}
+
+impl Mode {
+    pub fn from_param(x: f32) -> Mode {
+        match x.round() as i32 {
+            0 => Mode::Compress,
+            1 => Mode::Expand,
+            2 => Mode::Gate,
+            3 => Mode::Upward,
+            _ => Mode::Custom,
+        }
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Mode::Compress => "Compressor",
+            Mode::Expand => "Expander",
+            Mode::Gate => "Gate",
+            Mode::Upward => "Upward",
+            Mode::Custom => "Custom",
+        }
+    }
+}
+
+//What the key goes through before the RMS window & gain computer
+#[derive(Clone, Copy, PartialEq)]
+pub enum Detector {
+    Peak,
+    //Inter-sample peaks, see true_peak
+    TruePeak,
+    //Loudness weighted, see k_weighting. Combined with the RMS window this follows
+    //perceived loudness rather than low end energy.
+    KWeighted,
 }
 
-impl DecoupledPeakDetector {
-    pub fn new(attack: f32, release: f32, sample_rate: f32) -> DecoupledPeakDetector {
+impl Detector {
+    pub fn from_param(x: f32) -> Detector {
+        match x.round() as i32 {
+            0 => Detector::Peak,
+            1 => Detector::TruePeak,
+            _ => Detector::KWeighted,
+        }
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Detector::Peak => "Peak",
+            Detector::TruePeak => "True Peak",
+            Detector::KWeighted => "K-Weighted",
+        }
+    }
+}
+
+//Over-easy reaches the set ratio no less than this far above the bottom of its knee, dB
+const OVER_EASY_MIN_SPAN: f64 = 6.0;
+
+//Shape of the compressor's static curve around the threshold
+#[derive(Clone, Copy, PartialEq)]
+pub enum KneeShape {
+    //Quadratic blend across the knee width, see reiss
+    Quadratic,
+    //Straight from unity to the ratio at threshold, ignores the knee width
+    Hard,
+    //Softplus blend in the dB domain, eases into both slopes without a corner
+    Exponential,
+    //The ratio rises from 1.0 at the bottom of the knee, reaches the set ratio at the top
+    //and keeps rising above it
+    OverEasy,
+}
+
+impl KneeShape {
+    pub fn from_param(x: f32) -> KneeShape {
+        match x.round() as i32 {
+            0 => KneeShape::Quadratic,
+            1 => KneeShape::Hard,
+            2 => KneeShape::Exponential,
+            _ => KneeShape::OverEasy,
+        }
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            KneeShape::Quadratic => "Quadratic",
+            KneeShape::Hard => "Hard",
+            KneeShape::Exponential => "Exponential",
+            KneeShape::OverEasy => "Over-Easy",
+        }
+    }
+
+    //Output level of the static curve
+    //x, threshold, & width units are dB
+    //slope is: 1.0 / ratio - 1.0 (Computed ahead of time for performance)
+    pub fn apply<T: Float>(self, x: T, threshold: T, width: T, ratio: T, slope: T) -> T {
+        let zero = T::default();
+        let one = T::from_f64(1.0);
+        let two = T::from_f64(2.0);
+        match self {
+            KneeShape::Quadratic => reiss(x, threshold, width, ratio, slope),
+            KneeShape::Hard => reiss(x, threshold, zero, ratio, slope),
+            KneeShape::Exponential => {
+                if width <= zero {
+                    return reiss(x, threshold, zero, ratio, slope);
+                }
+                //Most of the bend falls inside the knee width
+                let scale = width / T::from_f64(4.0);
+                let u = (x - threshold) / scale;
+                let softplus = u.max(zero) + (-u.abs()).exp().ln_1p();
+                x + slope * scale * softplus
+            }
+            KneeShape::OverEasy => {
+                let start = threshold - width / two;
+                if x <= start || ratio <= one {
+                    return x;
+                }
+                //The ratio is 1.0 + (ratio - 1.0) * (x - start) / span
+                let span = width.max(T::from_f64(OVER_EASY_MIN_SPAN));
+                let rise = ratio - one;
+                start + span / rise * (rise * (x - start) / span).ln_1p()
+            }
+        }
+    }
+}
+
+//Makeup that compensates for half of the static curve's reduction at 0dBFS, units are dB
+//Only half, as program material sits well below full scale
+pub fn curve_makeup(threshold: f32, knee: f32, ratio: f32, shape: KneeShape) -> f32 {
+    0.5 * -shape.apply(0.0, threshold, knee, ratio, 1.0 / ratio - 1.0)
+}
+
+//One pole smoothing coefficient for a time constant in ms, computed in the sample type so
+//the f64 path keeps its precision at long times & high sample rates
+fn coefficient<T: Float>(time: f32, sample_rate: f32) -> T {
+    (-T::PI * T::from_f64(1000.0) / T::from_f32(time) / T::from_f32(sample_rate)).exp()
+}
+
+pub struct DecoupledPeakDetector<T: Float> {
+    attack: T,
+    release: T,
+    env: T,
+    env2: T,
+
+    //Samples to hold the peak for before releasing
+    hold: usize,
+    hold_counter: usize,
+
+    //Dual time constant auto release: a fast stage recovers from transients while a
+    //slow stage, which only charges up under sustained reduction, holds the rest
+    auto_release: bool,
+    auto_shape: T,
+    slow_attack: T,
+    slow_release: T,
+    env_slow: T,
+
+    //attack, hold & release in ms, kept for sample rate changes
+    times: (f32, f32, f32),
+    sample_rate: f32,
+    //Lowest attack coefficient, see limit_attack
+    min_attack: T,
+}
+
+impl<T: Float> DecoupledPeakDetector<T> {
+    pub fn new(attack: f32, hold: f32, release: f32, sample_rate: f32) -> DecoupledPeakDetector<T> {
         let mut detector = DecoupledPeakDetector {
-            attack: 0.0,
-            release: 0.0,
-            env: 0.0,
-            env2: 0.0,
+            attack: T::default(),
+            release: T::default(),
+            env: T::default(),
+            env2: T::default(),
+
+            hold: 0,
+            hold_counter: 0,
+
+            auto_release: false,
+            auto_shape: T::from_f64(1.0),
+            slow_attack: T::default(),
+            slow_release: T::default(),
+            env_slow: T::default(),
+
+            times: (attack, hold, release),
+            sample_rate,
+            min_attack: T::default(),
         };
-        detector.update(attack, release, sample_rate);
+        detector.update(attack, hold, release, sample_rate);
         detector
     }
 
-    pub fn process(&mut self, x: f32) -> f32 {
-        self.env = x.max(self.release * self.env);
-        self.env2 = self.attack * self.env2 + (1.0 - self.attack) * self.env;
+    pub fn reset(&mut self) {
+        self.env = T::default();
+        self.env2 = T::default();
+        self.env_slow = T::default();
+        self.hold_counter = 0;
+    }
+
+    //Counts down the hold time, returns true while the peak is held
+    fn holding(&mut self, x: T) -> bool {
+        if x >= self.env {
+            self.hold_counter = self.hold;
+            false
+        } else if self.hold_counter > 0 {
+            self.hold_counter -= 1;
+            true
+        } else {
+            false
+        }
+    }
+
+    pub fn process(&mut self, x: T) -> T {
+        let one = T::from_f64(1.0);
+        if !self.holding(x) {
+            self.env = x.max(self.release * self.env);
+        }
+        self.env2 = self.attack * self.env2 + (one - self.attack) * self.env;
         self.env2
     }
 
-    pub fn process_smooth(&mut self, x: f32) -> f32 {
-        self.env = x.max(self.release * self.env + (1.0 - self.release) * x);
-        self.env2 = self.attack * self.env2 + (1.0 - self.attack) * self.env;
+    pub fn process_smooth(&mut self, x: T) -> T {
+        let one = T::from_f64(1.0);
+        if !self.holding(x) {
+            self.env = x.max(self.release * self.env + (one - self.release) * x);
+        }
+        let env = if self.auto_release {
+            let coeff = if self.env > self.env_slow {
+                self.slow_attack
+            } else {
+                self.slow_release
+            };
+            self.env_slow = coeff * self.env_slow + (one - coeff) * self.env;
+            self.env_slow = if self.env_slow.is_finite() {
+                self.env_slow
+            } else {
+                one
+            };
+            self.env.max(self.env_slow * self.auto_shape)
+        } else {
+            self.env
+        };
+        self.env2 = self.attack * self.env2 + (one - self.attack) * env;
 
-        self.env = if self.env.is_finite() { self.env } else { 1.0 };
+        self.env = if self.env.is_finite() { self.env } else { one };
         self.env2 = if self.env2.is_finite() {
             self.env2
         } else {
-            1.0
+            one
         };
         self.env2
     }
 
-    pub fn update(&mut self, attack: f32, release: f32, sample_rate: f32) {
-        self.attack = (-1.0 * PI * 1000.0 / attack / sample_rate).exp();
-        self.release = (-1.0 * PI * 1000.0 / release / sample_rate).exp();
+    pub fn process_smooth_block(&mut self, buffer: &mut [T]) {
+        for x in buffer.iter_mut() {
+            *x = self.process_smooth(*x);
+        }
+    }
+
+    //attack, hold & release units are ms
+    //A release above AUTO_RELEASE selects the auto release. Clears any limit_attack.
+    pub fn update(&mut self, attack: f32, hold: f32, release: f32, sample_rate: f32) {
+        self.times = (attack, hold, release);
+        self.sample_rate = sample_rate;
+        self.hold = (hold * 0.001 * sample_rate).round().max(0.0) as usize;
+        self.hold_counter = self.hold_counter.min(self.hold);
+        self.auto_release = release > AUTO_RELEASE;
+        let release = if self.auto_release {
+            AUTO_FAST_RELEASE
+        } else {
+            release
+        };
+        self.attack = coefficient(attack, sample_rate);
+        self.min_attack = T::default();
+        self.release = coefficient(release, sample_rate);
+        self.slow_attack = coefficient(AUTO_SLOW_ATTACK, sample_rate);
+        self.slow_release = coefficient(AUTO_SLOW_RELEASE, sample_rate);
+    }
+
+    //0.0 - 1.0, how much of the slow stage's reduction is held. At 0.0 the auto release
+    //is a plain fast release.
+    pub fn set_auto_shape(&mut self, shape: f32) {
+        self.auto_shape = T::from_f32(shape);
+    }
+
+    //Keeps the attack smoothing coefficient at or above min_attack until the next update
+    pub fn limit_attack(&mut self, min_attack: f32) {
+        self.min_attack = T::from_f32(min_attack);
+        self.attack = self.attack.max(self.min_attack);
     }
 }
 
-pub struct Compressor {
-    envelope: f32,
-    threshold: f32,
-    knee: f32,
-    ratio: f32,
-    gain: f32,
+//Runs process_smooth. A new sample rate keeps the times and any limit_attack, the limit is
+//on the coefficient and doesn't depend on the sample rate.
+impl<T: Float> Processor<T> for DecoupledPeakDetector<T> {
+    fn reset(&mut self) {
+        DecoupledPeakDetector::reset(self);
+    }
 
-    slope: f32,
+    fn set_sample_rate(&mut self, sample_rate: f32) {
+        if sample_rate != self.sample_rate {
+            let (attack, hold, release) = self.times;
+            let min_attack = self.min_attack;
+            self.update(attack, hold, release, sample_rate);
+            self.limit_attack(min_attack.to_f32());
+        }
+    }
 
-    pre_smooth_gain: f32,
-    decoupled_peak_detector: DecoupledPeakDetector,
-    rms_size: f32,
-    rms: AccumulatingRMS,
+    fn process(&mut self, x: T) -> T {
+        self.process_smooth(x)
+    }
+
+    fn process_block(&mut self, buffer: &mut [T]) {
+        self.process_smooth_block(buffer);
+    }
 }
 
-impl Compressor {
-    pub fn new() -> Compressor {
-        Compressor {
-            envelope: 0.0,
-            threshold: 0.0,
-            knee: 0.0,
-            ratio: 0.0,
-            gain: 0.0,
+//attack, hold, release, ratio, feedback, mode & sample rate
+type Ballistics = (f32, f32, f32, f32, f32, Mode, f32);
 
-            slope: 0.0,
+//The RMS window & pre-smoothing as a node: takes the key and returns its level
+pub struct LevelDetector<T: Float> {
+    envelope: T,
+    //Time constant of the pre-smoothing, ms
+    pre_smooth: f32,
+    pre_smooth_gain: T,
+    rms_size: f32,
+    rms: AccumulatingRMS<T>,
+    sample_rate: f32,
+}
 
-            pre_smooth_gain: 0.0,
-            decoupled_peak_detector: DecoupledPeakDetector::new(0.0, 0.0, 48000.0),
+impl<T: Float> Default for LevelDetector<T> {
+    fn default() -> LevelDetector<T> {
+        LevelDetector::new()
+    }
+}
 
+impl<T: Float> LevelDetector<T> {
+    pub fn new() -> LevelDetector<T> {
+        LevelDetector {
+            envelope: T::default(),
+            pre_smooth: 0.0,
+            pre_smooth_gain: T::default(),
             rms_size: 0.0,
             rms: AccumulatingRMS::new(48000, 5.0, 192000),
+            sample_rate: 48000.0,
         }
     }
 
+    //pre_smooth & rms_size units are ms, an rms_size under 1.0 turns the RMS window off
+    pub fn update(&mut self, pre_smooth: f32, rms_size: f32, sample_rate: f32) {
+        if pre_smooth != self.pre_smooth || sample_rate != self.sample_rate {
+            self.pre_smooth_gain = coefficient(pre_smooth / 2.0, sample_rate);
+        }
+        if rms_size != self.rms_size || sample_rate != self.sample_rate {
+            self.rms.resize(sample_rate as usize, rms_size);
+        }
+        self.pre_smooth = pre_smooth;
+        self.rms_size = rms_size;
+        self.sample_rate = sample_rate;
+    }
+}
+
+impl<T: Float> Processor<T> for LevelDetector<T> {
+    fn reset(&mut self) {
+        self.envelope = T::default();
+        Processor::reset(&mut self.rms);
+    }
+
+    fn set_sample_rate(&mut self, sample_rate: f32) {
+        self.update(self.pre_smooth, self.rms_size, sample_rate);
+    }
+
+    fn process(&mut self, x: T) -> T {
+        let mut x = x.abs();
+        if self.rms_size >= 1.0 {
+            x = self.rms.process(x);
+        }
+        self.envelope = x + self.pre_smooth_gain * (self.envelope - x);
+        self.envelope = if self.envelope.is_finite() {
+            self.envelope
+        } else {
+            T::from_f64(1.0)
+        };
+        self.envelope
+    }
+
+    fn process_block(&mut self, buffer: &mut [T]) {
+        let one = T::from_f64(1.0);
+        for x in buffer.iter_mut() {
+            *x = x.abs();
+        }
+        if self.rms_size >= 1.0 {
+            self.rms.process_block(buffer);
+        }
+        let pre_smooth_gain = self.pre_smooth_gain;
+        let mut envelope = self.envelope;
+        for x in buffer.iter_mut() {
+            envelope = *x + pre_smooth_gain * (envelope - *x);
+            envelope = if envelope.is_finite() { envelope } else { one };
+            *x = envelope;
+        }
+        self.envelope = envelope;
+    }
+}
+
+//The static curve & ballistics as a node: takes the level from a LevelDetector and returns
+//the attenuation multiplier, or the boost multiplier in Mode::Upward
+pub struct GainComputer<T: Float> {
+    threshold: T,
+    knee: T,
+    ratio: T,
+    gain: T,
+    threshold_smoother: Smoother<T>,
+    knee_smoother: Smoother<T>,
+    ratio_smoother: Smoother<T>,
+    //Ramp time of the smoothers, ms
+    smoothing: f32,
+
+    slope: T,
+    feedback_ratio: T,
+    feedback_slope: T,
+
+    decoupled_peak_detector: DecoupledPeakDetector<T>,
+    sample_rate: f32,
+
+    //0.0 is feed-forward, 1.0 is feedback, in between blends the two gain computers
+    feedback: f32,
+    hold: f32,
+    last_cv: T,
+
+    mode: Mode,
+    //Maximum attenuation of the expander & gate, maximum boost of upward compression, dB
+    range: T,
+    //The gate opens at threshold and closes at threshold - hysteresis, dB
+    hysteresis: T,
+    //Upward compression leaves anything below this alone, dB
+    noise_floor: T,
+    gate_open: bool,
+    knee_shape: KneeShape,
+    //Static curve of Mode::Custom
+    curve: TransferCurve,
+
+    //Everything the detector's coefficients were last computed from
+    ballistics: Ballistics,
+}
+
+impl<T: Float> Default for GainComputer<T> {
+    fn default() -> GainComputer<T> {
+        GainComputer::new()
+    }
+}
+
+impl<T: Float> GainComputer<T> {
+    pub fn new() -> GainComputer<T> {
+        GainComputer {
+            threshold: T::default(),
+            knee: T::default(),
+            ratio: T::from_f64(1.0),
+            gain: T::default(),
+            threshold_smoother: Smoother::new(T::default()),
+            knee_smoother: Smoother::new(T::default()),
+            ratio_smoother: Smoother::new(T::from_f64(1.0)),
+            smoothing: 0.0,
+
+            slope: T::default(),
+            feedback_ratio: T::from_f64(1.0),
+            feedback_slope: T::default(),
+
+            decoupled_peak_detector: DecoupledPeakDetector::new(0.0, 0.0, 0.0, 48000.0),
+            sample_rate: 48000.0,
+
+            feedback: 0.0,
+            hold: 0.0,
+            last_cv: T::from_f64(1.0),
+
+            mode: Mode::Compress,
+            range: T::default(),
+            hysteresis: T::default(),
+            noise_floor: T::from_f64(-70.0),
+            gate_open: false,
+            knee_shape: KneeShape::Quadratic,
+            curve: TransferCurve::new(&[]),
+
+            ballistics: (0.0, 0.0, 0.0, 0.0, 0.0, Mode::Compress, 0.0),
+        }
+    }
+
+    //Ramp time in ms for threshold, knee & ratio changes
+    pub fn set_smoothing(&mut self, smoothing: f32) {
+        if smoothing != self.smoothing {
+            self.smoothing = smoothing;
+            self.set_ramps(self.sample_rate);
+        }
+    }
+
+    //Takes effect on the next update_prams
+    pub fn set_feedback(&mut self, feedback: f32) {
+        self.feedback = feedback;
+    }
+
+    //range, hysteresis & noise_floor are not used by Mode::Compress
+    pub fn set_mode(&mut self, mode: Mode, range: f32, hysteresis: f32, noise_floor: f32) {
+        self.mode = mode;
+        self.range = T::from_f32(range);
+        self.hysteresis = T::from_f32(hysteresis);
+        self.noise_floor = T::from_f32(noise_floor);
+    }
+
+    //Used by Mode::Compress & Mode::Upward
+    pub fn set_knee_shape(&mut self, shape: KneeShape) {
+        self.knee_shape = shape;
+    }
+
+    //Only used by Mode::Custom
+    pub fn set_transfer_curve(&mut self, curve: TransferCurve) {
+        self.curve = curve;
+    }
+
+    //Hold time in ms, takes effect on the next update_prams
+    pub fn set_hold(&mut self, hold: f32) {
+        self.hold = hold;
+    }
+
+    pub fn set_auto_release_shape(&mut self, shape: f32) {
+        self.decoupled_peak_detector.set_auto_shape(shape);
+    }
+
+    //threshold, knee & ratio are ramped to per sample in process. The detector's coefficients
+    //are only recomputed when something they depend on changes.
+    #[allow(clippy::too_many_arguments)]
     pub fn update_prams(
         &mut self,
         threshold: f32,
         knee: f32,
-        pre_smooth: f32,
-        rms_size: f32,
         ratio: f32,
         attack: f32,
         release: f32,
         gain: f32,
         sample_rate: f32,
     ) {
-        //TODO don't update here unnecessarily
-        self.ratio = ratio;
-        self.gain = db_to_lin(gain);
-        self.threshold = threshold;
-        self.knee = knee;
+        self.gain = db_to_lin(T::from_f32(gain));
+
+        self.set_sample_rate(sample_rate);
+        self.threshold_smoother.set_target(T::from_f32(threshold));
+        self.knee_smoother.set_target(T::from_f32(knee));
+        self.ratio_smoother.set_target(T::from_f32(ratio));
+
+        //While the ratio ramps the higher of the two sets the attack limit
+        let limit_ratio = ratio.max(self.ratio.to_f32());
+        self.update_ballistics((
+            attack,
+            self.hold,
+            release,
+            limit_ratio,
+            self.feedback,
+            self.mode,
+            sample_rate,
+        ));
+    }
+
+    //Recomputes everything that depends on the sample rate, update_prams calls this
+    pub fn set_sample_rate(&mut self, sample_rate: f32) {
+        if sample_rate != self.sample_rate {
+            self.sample_rate = sample_rate;
+            self.set_ramps(sample_rate);
+            let mut ballistics = self.ballistics;
+            ballistics.6 = sample_rate;
+            self.update_ballistics(ballistics);
+        }
+    }
+
+    pub fn reset(&mut self) {
+        self.last_cv = T::from_f64(1.0);
+        self.gate_open = false;
+        self.decoupled_peak_detector.reset();
+    }
+
+    fn update_ballistics(&mut self, ballistics: Ballistics) {
+        if ballistics == self.ballistics {
+            return;
+        }
+        self.ballistics = ballistics;
+        let (attack, hold, release, limit_ratio, feedback, mode, sample_rate) = ballistics;
 
-        self.slope = 1.0 / self.ratio - 1.0;
-        self.pre_smooth_gain = (-2.0 * PI * 1000.0 / pre_smooth / sample_rate).exp();
         self.decoupled_peak_detector
-            .update(attack, release, sample_rate);
+            .update(attack, hold, release, sample_rate);
 
-        if rms_size != self.rms_size {
-            self.rms_size = rms_size;
-            self.rms.resize(sample_rate as usize, self.rms_size)
+        //The loop gain is feedback * (ratio - 1), with a fast attack at high ratios each
+        //sample would overshoot the last and oscillate. Slowing the attack keeps the loop's
+        //pole inside the unit circle, with a 2x margin for the pre-smoothing lag.
+        let loop_gain = 2.0 * feedback * (limit_ratio - 1.0);
+        if mode == Mode::Compress && loop_gain > 1.0 {
+            self.decoupled_peak_detector
+                .limit_attack((loop_gain - 1.0) / (loop_gain + 1.0));
         }
     }
 
-    //To make detector_input from stereo:
-    //detector_input = (input_l + input_r).abs() * 0.5
-    //Returns attenuation multiplier
-    pub fn process(&mut self, detector_input: f32) -> f32 {
-        let mut detector_input = detector_input;
-        if self.rms_size >= 1.0 {
-            detector_input = self.rms.process(detector_input);
+    fn set_ramps(&mut self, sample_rate: f32) {
+        for smoother in [
+            &mut self.threshold_smoother,
+            &mut self.knee_smoother,
+            &mut self.ratio_smoother,
+        ]
+        .iter_mut()
+        {
+            smoother.set_ramp(self.smoothing, sample_rate);
         }
+    }
 
-        self.envelope = detector_input + self.pre_smooth_gain * (self.envelope - detector_input);
+    fn set_ratio(&mut self, ratio: T) {
+        let one = T::from_f64(1.0);
+        self.ratio = ratio;
+        self.slope = one / self.ratio - one;
+        //In feedback the detector hears the already compressed output, which is
+        //(x - threshold) / ratio above threshold. A slope of (1 - ratio) instead of
+        //(1 / ratio - 1) gives the same static curve as feed-forward.
+        self.feedback_slope = one - ratio;
+        self.feedback_ratio = one / (one + self.feedback_slope);
+    }
 
-        self.envelope = if self.envelope.is_finite() {
-            self.envelope
+    //Output level in dB of the feed-forward static curve
+    fn static_curve(&self, db: T) -> T {
+        if self.mode == Mode::Custom {
+            self.curve.output(db)
         } else {
-            1.0
+            self.knee_shape
+                .apply(db, self.threshold, self.knee, self.ratio, self.slope)
+        }
+    }
+
+    //Advances threshold, knee & ratio by one sample
+    fn next_curve(&mut self) {
+        self.threshold = self.threshold_smoother.next();
+        self.knee = self.knee_smoother.next();
+        let ratio = self.ratio_smoother.next();
+        if ratio != self.ratio {
+            self.set_ratio(ratio);
+        }
+    }
+
+    //Block version of process, replaces each level with its attenuation multiplier.
+    //Feed-forward compression & the custom curve run as stages over the whole block, with
+    //the dB conversions in their own loops so they can be vectorized. Feedback and the other
+    //modes need each sample's result for the next one, they go through process.
+    pub fn process_block(&mut self, buffer: &mut [T]) {
+        let feed_forward = match self.mode {
+            Mode::Compress => self.feedback <= 0.0,
+            Mode::Custom => true,
+            _ => false,
         };
+        if !feed_forward {
+            for x in buffer.iter_mut() {
+                *x = self.process(*x);
+            }
+            return;
+        }
+        let one = T::from_f64(1.0);
+
+        for x in buffer.iter_mut() {
+            *x = lin_to_db(*x);
+        }
+
+        for x in buffer.iter_mut() {
+            self.next_curve();
+            *x = *x - self.static_curve(*x);
+        }
+
+        self.decoupled_peak_detector.process_smooth_block(buffer);
 
-        let db = lin_to_db(self.envelope);
+        for x in buffer.iter_mut() {
+            let cv = db_to_lin(-*x);
+            *x = if cv.is_finite() { cv } else { one };
+        }
+        if let Some(cv) = buffer.last() {
+            self.last_cv = *cv;
+        }
+    }
 
-        let mut cv = db - reiss(db, self.threshold, self.knee, self.ratio, self.slope);
+    //level is the LevelDetector's output
+    //Returns attenuation multiplier
+    pub fn process(&mut self, level: T) -> T {
+        let one = T::from_f64(1.0);
+        self.next_curve();
+
+        let db = lin_to_db(level);
+
+        match self.mode {
+            Mode::Compress | Mode::Custom => (),
+            Mode::Upward => return self.process_upward(db),
+            _ => return self.process_expander(db),
+        }
+
+        //The custom curve is always feed-forward, it has no feedback equivalent
+        let mut cv = db - self.static_curve(db);
+        if self.feedback > 0.0 && self.mode == Mode::Compress {
+            //Level of the previous output, assuming the detector hears the audio being compressed
+            let output_db = lin_to_db(level * self.last_cv);
+            //Over-easy's rising ratio can't be inverted, its feedback uses the quadratic knee
+            let shape = if self.knee_shape == KneeShape::OverEasy {
+                KneeShape::Quadratic
+            } else {
+                self.knee_shape
+            };
+            let feedback_cv = output_db
+                - shape.apply(
+                    output_db,
+                    self.threshold,
+                    self.knee,
+                    self.feedback_ratio,
+                    self.feedback_slope,
+                );
+            cv += T::from_f32(self.feedback) * (feedback_cv - cv);
+        }
         cv = db_to_lin(-self.decoupled_peak_detector.process_smooth(cv));
-        if cv.is_finite() {
-            cv
+        self.last_cv = if cv.is_finite() { cv } else { one };
+        self.last_cv
+    }
+
+    fn process_expander(&mut self, db: T) -> T {
+        let reduction = if self.mode == Mode::Gate {
+            if self.gate_open {
+                self.gate_open = db >= self.threshold - self.hysteresis;
+            } else {
+                self.gate_open = db >= self.threshold;
+            }
+            if self.gate_open {
+                T::default()
+            } else {
+                self.range
+            }
+        } else {
+            (db - reiss_expander(db, self.threshold, self.knee, self.ratio)).min(self.range)
+        };
+        //The detector runs on how far open the gate is, so opening uses the attack and
+        //closing waits for the hold then follows the release
+        let open = self
+            .decoupled_peak_detector
+            .process_smooth(self.range - reduction);
+        let cv = db_to_lin(open - self.range);
+        self.last_cv = if cv.is_finite() { cv } else { T::from_f64(1.0) };
+        self.last_cv
+    }
+
+    //Returns a boost multiplier
+    fn process_upward(&mut self, db: T) -> T {
+        let two = T::from_f64(2.0);
+        let fade = T::from_f64(NOISE_FLOOR_FADE);
+        let boost = if db <= self.noise_floor - fade {
+            T::default()
         } else {
-            1.0
+            //The compressor's curve mirrored around the threshold
+            let mirrored = two * self.threshold - db;
+            let y = two * self.threshold
+                - self.knee_shape.apply(
+                    mirrored,
+                    self.threshold,
+                    self.knee,
+                    self.ratio,
+                    self.slope,
+                );
+            //Full boost from the noise floor up, fading to none NOISE_FLOOR_FADE dB below it,
+            //so material crossing the floor isn't switched in & out
+            let amount = ((db - self.noise_floor + fade) / fade).min(T::from_f64(1.0));
+            (y - db).min(self.range) * amount
+        };
+        //The detector runs on how far the boost is below its maximum, so pulling the
+        //boost back uses the attack and raising it follows the hold & release
+        let env = self
+            .decoupled_peak_detector
+            .process_smooth(self.range - boost);
+        let cv = db_to_lin(self.range - env);
+        self.last_cv = if cv.is_finite() { cv } else { T::from_f64(1.0) };
+        self.last_cv
+    }
+}
+
+impl<T: Float> Processor<T> for GainComputer<T> {
+    fn reset(&mut self) {
+        GainComputer::reset(self);
+    }
+
+    fn set_sample_rate(&mut self, sample_rate: f32) {
+        GainComputer::set_sample_rate(self, sample_rate);
+    }
+
+    fn process(&mut self, x: T) -> T {
+        GainComputer::process(self, x)
+    }
+
+    fn process_block(&mut self, buffer: &mut [T]) {
+        GainComputer::process_block(self, buffer);
+    }
+}
+
+//One channel of the compressor: takes the key and returns the attenuation multiplier.
+//To make the key from stereo see stereo::detector_inputs.
+pub type Compressor<T> = Chain<LevelDetector<T>, GainComputer<T>>;
+
+impl<T: Float> Compressor<T> {
+    pub fn new() -> Compressor<T> {
+        LevelDetector::new().then(GainComputer::new())
+    }
+
+    //The gain computer's settings plus pre_smooth & rms_size for the level detector
+    pub fn update_prams(
+        &mut self,
+        threshold: f32,
+        knee: f32,
+        pre_smooth: f32,
+        rms_size: f32,
+        ratio: f32,
+        attack: f32,
+        release: f32,
+        gain: f32,
+        sample_rate: f32,
+    ) {
+        self.first.update(pre_smooth, rms_size, sample_rate);
+        self.second
+            .update_prams(threshold, knee, ratio, attack, release, gain, sample_rate);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::compressor_effect_parameters::CompressorEffectParameters;
+
+    const SHAPES: [KneeShape; 4] = [
+        KneeShape::Quadratic,
+        KneeShape::Hard,
+        KneeShape::Exponential,
+        KneeShape::OverEasy,
+    ];
+    //Settings tried across each parameter's range, ends included
+    const STEPS: usize = 8;
+    //Input sweep, dB
+    const SWEEP_MARGIN: f32 = 60.0;
+    const SWEEP_STEP: f32 = 0.05;
+
+    //Calls check with every shape over a grid of the threshold, knee & ratio parameter ranges
+    fn for_each_curve<F: FnMut(KneeShape, f32, f32, f32)>(mut check: F) {
+        let params = CompressorEffectParameters::default();
+        let grid = |min: f32, max: f32| {
+            (0..STEPS).map(move |i| min + (max - min) * i as f32 / (STEPS - 1) as f32)
+        };
+        for &shape in SHAPES.iter() {
+            for threshold in grid(params.threshold.min, params.threshold.max) {
+                for knee in grid(params.knee.min, params.knee.max) {
+                    for ratio in grid(params.ratio.min, params.ratio.max) {
+                        check(shape, threshold, knee, ratio);
+                    }
+                }
+            }
         }
     }
+
+    //Output levels over the threshold range plus a margin either side, in steps of SWEEP_STEP
+    fn sweep<T: Float>(shape: KneeShape, threshold: f32, knee: f32, ratio: f32) -> Vec<(T, T)> {
+        let params = CompressorEffectParameters::default();
+        let bottom = params.threshold.min - SWEEP_MARGIN;
+        let steps = ((params.threshold.max + SWEEP_MARGIN - bottom) / SWEEP_STEP) as usize;
+        let (threshold, knee, ratio) = (
+            T::from_f32(threshold),
+            T::from_f32(knee),
+            T::from_f32(ratio),
+        );
+        let slope = T::from_f64(1.0) / ratio - T::from_f64(1.0);
+        (0..=steps)
+            .map(|i| {
+                let x = T::from_f32(bottom) + T::from_f32(SWEEP_STEP) * T::from_f64(i as f64);
+                (x, shape.apply(x, threshold, knee, ratio, slope))
+            })
+            .collect()
+    }
+
+    fn assert_monotonic<T: Float>(tolerance: f64) {
+        for_each_curve(|shape, threshold, knee, ratio| {
+            let curve = sweep::<T>(shape, threshold, knee, ratio);
+            for pair in curve.windows(2) {
+                let ((x, y0), (_, y1)) = (pair[0], pair[1]);
+                assert!(
+                    y1 >= y0 - T::from_f64(tolerance),
+                    "{} threshold {} knee {} ratio {} falls at {}",
+                    shape.name(),
+                    threshold,
+                    knee,
+                    ratio,
+                    x.to_f32()
+                );
+            }
+        });
+    }
+
+    //The slope of every shape is between 1.0 / ratio and 1.0, so between neighbouring inputs
+    //the output can't move further than the input did. A jump anywhere would break that.
+    fn assert_continuous<T: Float>(tolerance: f64) {
+        for_each_curve(|shape, threshold, knee, ratio| {
+            let curve = sweep::<T>(shape, threshold, knee, ratio);
+            for pair in curve.windows(2) {
+                let ((x0, y0), (x1, y1)) = (pair[0], pair[1]);
+                assert!(
+                    y0.is_finite() && (y1 - y0).abs() <= (x1 - x0) + T::from_f64(tolerance),
+                    "{} threshold {} knee {} ratio {} jumps at {}",
+                    shape.name(),
+                    threshold,
+                    knee,
+                    ratio,
+                    x0.to_f32()
+                );
+            }
+
+            //Right at the corners, where the pieces of each shape meet
+            let slope = 1.0 / ratio as f64 - 1.0;
+            let (threshold, knee, ratio) = (threshold as f64, knee as f64, ratio as f64);
+            let delta = 1e-9;
+            for &corner in [threshold, threshold - knee / 2.0, threshold + knee / 2.0].iter() {
+                let below = shape.apply(corner - delta, threshold, knee, ratio, slope);
+                let at = shape.apply(corner, threshold, knee, ratio, slope);
+                let above = shape.apply(corner + delta, threshold, knee, ratio, slope);
+                assert!(
+                    (at - below).abs() < 1e-6 && (above - at).abs() < 1e-6,
+                    "{} threshold {} knee {} ratio {} jumps at {}",
+                    shape.name(),
+                    threshold,
+                    knee,
+                    ratio,
+                    corner
+                );
+            }
+        });
+    }
+
+    #[test]
+    fn knee_shapes_are_monotonic() {
+        assert_monotonic::<f32>(1e-4);
+        assert_monotonic::<f64>(1e-9);
+    }
+
+    #[test]
+    fn knee_shapes_are_continuous() {
+        assert_continuous::<f32>(1e-4);
+        assert_continuous::<f64>(1e-9);
+    }
+
+    #[test]
+    fn curve_makeup_is_finite_at_defaults() {
+        let params = CompressorEffectParameters::default();
+        for &shape in SHAPES.iter() {
+            let makeup = curve_makeup(
+                params.threshold.get(),
+                params.knee.get(),
+                params.ratio.get(),
+                shape,
+            );
+            assert!(makeup.is_finite(), "{}", shape.name());
+        }
+    }
+
+    #[test]
+    fn limit_attack_survives_sample_rate_changes() {
+        let mut detector = DecoupledPeakDetector::<f32>::new(0.0, 0.0, 100.0, 48000.0);
+        detector.limit_attack(0.9);
+        Processor::set_sample_rate(&mut detector, 96000.0);
+        assert_eq!(detector.attack, 0.9);
+        //A new update is new settings, the limit has to be asked for again
+        detector.update(0.0, 0.0, 100.0, 96000.0);
+        assert!(detector.attack < 0.9);
+    }
 }
@@ -1,4 +1,11 @@
+use super::compressor::{Detector, KneeShape, Mode, AUTO_RELEASE};
+use super::multiband::MAX_BANDS;
+use super::oversampling::factor_name;
 use super::parameter::Parameter;
+use super::saturation::Curve;
+use super::sidechain_filter::{SC_FREQ_MAX, SC_FREQ_MIN};
+use super::stereo::StereoLink;
+use super::transfer_curve::{TransferCurve, MAX_POINTS};
 
 pub struct CompressorEffectParameters {
     // The plugin's state consists of a single parameter: amplitude.
@@ -10,9 +17,167 @@ pub struct CompressorEffectParameters {
     pub attack: Parameter,
     pub release: Parameter,
     pub gain: Parameter,
+    pub sidechain: Parameter,
+    pub sc_hp_freq: Parameter,
+    pub sc_lp_freq: Parameter,
+    pub sc_bell_freq: Parameter,
+    pub sc_bell_q: Parameter,
+    pub sc_bell_gain: Parameter,
+    pub stereo_link: Parameter,
+    pub link: Parameter,
+    pub mid_side: Parameter,
+    pub side_threshold: Parameter,
+    pub side_ratio: Parameter,
+    pub lookahead: Parameter,
+    pub oversampling: Parameter,
+    pub offline_oversampling: Parameter,
+    pub band_count: Parameter,
+    pub crossover_1: Parameter,
+    pub crossover_2: Parameter,
+    pub crossover_3: Parameter,
+    pub bands: [BandParameters; MAX_BANDS],
+    pub topology: Parameter,
+    pub auto_release_shape: Parameter,
+    pub hold: Parameter,
+    pub mode: Parameter,
+    pub range: Parameter,
+    pub hysteresis: Parameter,
+    pub noise_floor: Parameter,
+    pub mix: Parameter,
+    pub gain_on_sum: Parameter,
+    pub auto_makeup: Parameter,
+    pub detector: Parameter,
+    pub meters: Parameter,
+    pub true_peak_ceiling: Parameter,
+    pub limiter: Parameter,
+    pub limiter_ceiling: Parameter,
+    pub saturation: Parameter,
+    pub drive: Parameter,
+    pub saturation_position: Parameter,
+    pub smoothing: Parameter,
+    pub curve_points: [CurvePointParameters; MAX_POINTS],
+    pub knee_shape: Parameter,
+    //Whether MIDI control changes set parameters, see CompressorPlugin::process_events
+    pub midi_automation: Parameter,
+    //Changes waiting for the next processed block, sorted by offset
+    pub scheduled: Mutex<Vec<ParameterEvent>>,
 }
 
+pub const MAX_PARAMETER_EVENTS: usize = 512;
+
+//A normalized parameter value to set at a sample offset into the next processed block
+#[derive(Clone, Copy)]
+pub struct ParameterEvent {
+    pub offset: usize,
+    pub index: usize,
+    pub value: f32,
+}
+
+//Used in multiband mode instead of the main threshold, ratio, attack & release
+pub struct BandParameters {
+    pub threshold: Parameter,
+    pub ratio: Parameter,
+    pub attack: Parameter,
+    pub release: Parameter,
+    pub gain: Parameter,
+    pub bypass: Parameter,
+}
+
+//A breakpoint of the custom transfer curve, used in the Custom mode
+pub struct CurvePointParameters {
+    pub input: Parameter,
+    pub output: Parameter,
+}
+
+//The top of the release range is the auto release. It spans the last 100ms of the range,
+//about a tenth of the knob's travel, so it's easy to land on.
+const RELEASE_MAX: f32 = AUTO_RELEASE + 100.0;
+
+fn release_display(x: f32) -> String {
+    if x > AUTO_RELEASE {
+        "Auto".to_string()
+    } else {
+        format!("{:.2}ms", x)
+    }
+}
+
+const BAND_PARAMETERS_LEN: usize = 6;
+const BANDS_START: usize = 26;
+const BANDS_END: usize = BANDS_START + MAX_BANDS * BAND_PARAMETERS_LEN;
+
+const CURVE_POINT_PARAMETERS_LEN: usize = 2;
+const CURVE_POINTS_START: usize = 69;
+const CURVE_POINTS_END: usize = CURVE_POINTS_START + MAX_POINTS * CURVE_POINT_PARAMETERS_LEN;
+
+//Gentle 2:1 from -30dB, then 4:1 from -20dB
+const DEFAULT_CURVE: [(f32, f32); MAX_POINTS] = [
+    (-80.0, -80.0),
+    (-40.0, -40.0),
+    (-30.0, -30.0),
+    (-20.0, -25.0),
+    (-10.0, -22.5),
+    (0.0, -20.0),
+];
+
 use std::ops::Index;
+use std::sync::Mutex;
+
+impl Index<usize> for BandParameters {
+    type Output = Parameter;
+    fn index(&self, i: usize) -> &Self::Output {
+        match i {
+            0 => &self.threshold,
+            1 => &self.ratio,
+            2 => &self.attack,
+            3 => &self.release,
+            4 => &self.gain,
+            _ => &self.bypass,
+        }
+    }
+}
+
+impl BandParameters {
+    fn new(band: usize) -> BandParameters {
+        let name = |x| format!("B{} {}", band + 1, x);
+        BandParameters {
+            threshold: Parameter::new(&name("Threshold"), 0.0, -80.0, 12.0, |x| {
+                format!("{:.2}dB", x)
+            }),
+            ratio: Parameter::new(&name("Ratio"), 4.0, 1.0, 20.0, |x| format!("{:.2}", x)),
+            attack: Parameter::new(&name("Attack"), 1.0, 0.0, 300.0, |x| format!("{:.2}ms", x)),
+            release: Parameter::new(&name("Release"), 100.0, 0.0, RELEASE_MAX, release_display),
+            gain: Parameter::new(&name("Gain"), 0.0, -24.0, 24.0, |x| format!("{:.2}dB", x)),
+            bypass: Parameter::new(&name("Bypass"), 0.0, 0.0, 1.0, |x| {
+                if x < 0.5 {
+                    "Off".to_string()
+                } else {
+                    "On".to_string()
+                }
+            }),
+        }
+    }
+}
+
+impl Index<usize> for CurvePointParameters {
+    type Output = Parameter;
+    fn index(&self, i: usize) -> &Self::Output {
+        match i {
+            0 => &self.input,
+            _ => &self.output,
+        }
+    }
+}
+
+impl CurvePointParameters {
+    fn new(point: usize) -> CurvePointParameters {
+        let name = |x| format!("P{} {}", point + 1, x);
+        let (input, output) = DEFAULT_CURVE[point];
+        CurvePointParameters {
+            input: Parameter::new(&name("In"), input, -80.0, 12.0, |x| format!("{:.2}dB", x)),
+            output: Parameter::new(&name("Out"), output, -80.0, 12.0, |x| format!("{:.2}dB", x)),
+        }
+    }
+}
 
 impl Index<usize> for CompressorEffectParameters {
     type Output = Parameter;
@@ -26,14 +191,94 @@ impl Index<usize> for CompressorEffectParameters {
             5 => &self.attack,
             6 => &self.release,
             7 => &self.gain,
+            8 => &self.sidechain,
+            9 => &self.sc_hp_freq,
+            10 => &self.sc_lp_freq,
+            11 => &self.sc_bell_freq,
+            12 => &self.sc_bell_q,
+            13 => &self.sc_bell_gain,
+            14 => &self.stereo_link,
+            15 => &self.link,
+            16 => &self.mid_side,
+            17 => &self.side_threshold,
+            18 => &self.side_ratio,
+            19 => &self.lookahead,
+            20 => &self.oversampling,
+            21 => &self.offline_oversampling,
+            22 => &self.band_count,
+            23 => &self.crossover_1,
+            24 => &self.crossover_2,
+            25 => &self.crossover_3,
+            i if (BANDS_START..BANDS_END).contains(&i) => {
+                let i = i - BANDS_START;
+                &self.bands[i / BAND_PARAMETERS_LEN][i % BAND_PARAMETERS_LEN]
+            }
+            50 => &self.topology,
+            51 => &self.auto_release_shape,
+            52 => &self.hold,
+            53 => &self.mode,
+            54 => &self.range,
+            55 => &self.hysteresis,
+            56 => &self.noise_floor,
+            57 => &self.mix,
+            58 => &self.gain_on_sum,
+            59 => &self.auto_makeup,
+            60 => &self.detector,
+            61 => &self.meters,
+            62 => &self.true_peak_ceiling,
+            63 => &self.limiter,
+            64 => &self.limiter_ceiling,
+            65 => &self.saturation,
+            66 => &self.drive,
+            67 => &self.saturation_position,
+            68 => &self.smoothing,
+            i if (CURVE_POINTS_START..CURVE_POINTS_END).contains(&i) => {
+                let i = i - CURVE_POINTS_START;
+                &self.curve_points[i / CURVE_POINT_PARAMETERS_LEN][i % CURVE_POINT_PARAMETERS_LEN]
+            }
+            81 => &self.knee_shape,
+            82 => &self.midi_automation,
             _ => &self.gain,
         }
     }
 }
 
 impl CompressorEffectParameters {
+    #[allow(clippy::len_without_is_empty)]
     pub fn len(&self) -> usize {
-        8
+        83
+    }
+
+    //Sets a parameter at a sample offset into the next processed block, for a host or wrapper
+    //that knows when a change happens. VST2 doesn't, see set_parameter. Returns false if the
+    //queue is full or the index is out of range.
+    pub fn schedule(&self, offset: usize, index: usize, value: f32) -> bool {
+        if index >= self.len() {
+            return false;
+        }
+        let mut scheduled = self.scheduled.lock().unwrap();
+        if scheduled.len() >= MAX_PARAMETER_EVENTS {
+            return false;
+        }
+        //After any change already queued for the same offset, so the last one wins
+        let position = scheduled.partition_point(|event| event.offset <= offset);
+        scheduled.insert(
+            position,
+            ParameterEvent {
+                offset,
+                index,
+                value,
+            },
+        );
+        true
+    }
+
+    pub fn transfer_curve(&self) -> TransferCurve {
+        let mut points = [(0.0, 0.0); MAX_POINTS];
+        for (point, params) in points.iter_mut().zip(self.curve_points.iter()) {
+            *point = (params.input.get(), params.output.get());
+        }
+        TransferCurve::new(&points)
     }
 }
 
@@ -46,8 +291,161 @@ impl Default for CompressorEffectParameters {
             rms: Parameter::new("RMS", 5.0, 0.0, 100.0, |x| format!("{:.2}ms", x)),
             ratio: Parameter::new("Ratio", 4.0, 1.0, 20.0, |x| format!("{:.2}", x)),
             attack: Parameter::new("Attack", 1.0, 0.0, 300.0, |x| format!("{:.2}ms", x)),
-            release: Parameter::new("Release", 100.0, 0.0, 1000.0, |x| format!("{:.2}ms", x)),
+            release: Parameter::new("Release", 100.0, 0.0, RELEASE_MAX, release_display),
             gain: Parameter::new("Gain", 0.0, -24.0, 24.0, |x| format!("{:.2}dB", x)),
+            sidechain: Parameter::new("Sidechain", 0.0, 0.0, 1.0, |x| {
+                if x < 0.5 {
+                    "Internal".to_string()
+                } else {
+                    "External".to_string()
+                }
+            }),
+            sc_hp_freq: Parameter::new("SC HP", SC_FREQ_MIN, SC_FREQ_MIN, 2000.0, |x| {
+                if x <= SC_FREQ_MIN {
+                    "Off".to_string()
+                } else {
+                    format!("{:.0}Hz", x)
+                }
+            }),
+            sc_lp_freq: Parameter::new("SC LP", SC_FREQ_MAX, 1000.0, SC_FREQ_MAX, |x| {
+                if x >= SC_FREQ_MAX {
+                    "Off".to_string()
+                } else {
+                    format!("{:.0}Hz", x)
+                }
+            }),
+            sc_bell_freq: Parameter::new("SC Bell", 1000.0, SC_FREQ_MIN, SC_FREQ_MAX, |x| {
+                format!("{:.0}Hz", x)
+            }),
+            sc_bell_q: Parameter::new("SC Bell Q", 1.0, 0.1, 10.0, |x| format!("{:.2}", x)),
+            sc_bell_gain: Parameter::new("SC Bell Gain", 0.0, -24.0, 24.0, |x| {
+                format!("{:.2}dB", x)
+            }),
+            stereo_link: Parameter::new("Stereo Link", 1.0, 0.0, 3.0, |x| {
+                StereoLink::from_param(x).name().to_string()
+            }),
+            link: Parameter::new("Link", 100.0, 0.0, 100.0, |x| format!("{:.0}%", x)),
+            mid_side: Parameter::new("Stereo Mode", 0.0, 0.0, 1.0, |x| {
+                if x < 0.5 {
+                    "L/R".to_string()
+                } else {
+                    "M/S".to_string()
+                }
+            }),
+            side_threshold: Parameter::new("Side Threshold", 0.0, -24.0, 24.0, |x| {
+                format!("{:+.2}dB", x)
+            }),
+            side_ratio: Parameter::new("Side Ratio", 0.0, -19.0, 19.0, |x| format!("{:+.2}", x)),
+            lookahead: Parameter::new("Lookahead", 0.0, 0.0, 20.0, |x| format!("{:.2}ms", x)),
+            oversampling: Parameter::new("Oversampling", 0.0, 0.0, 3.0, factor_name),
+            offline_oversampling: Parameter::new("Offline OS", 0.0, 0.0, 3.0, factor_name),
+            band_count: Parameter::new("Bands", 1.0, 1.0, MAX_BANDS as f32, |x| {
+                match x.round() as usize {
+                    1 => "Off".to_string(),
+                    bands => format!("{}", bands),
+                }
+            }),
+            crossover_1: Parameter::new("Low X", 120.0, 20.0, 2000.0, |x| format!("{:.0}Hz", x)),
+            crossover_2: Parameter::new("Mid X", 1000.0, 100.0, 8000.0, |x| format!("{:.0}Hz", x)),
+            crossover_3: Parameter::new("High X", 5000.0, 500.0, 18000.0, |x| {
+                format!("{:.0}Hz", x)
+            }),
+            bands: [
+                BandParameters::new(0),
+                BandParameters::new(1),
+                BandParameters::new(2),
+                BandParameters::new(3),
+            ],
+            topology: Parameter::new("Topology", 0.0, 0.0, 100.0, |x| {
+                if x <= 0.0 {
+                    "Feed-forward".to_string()
+                } else if x >= 100.0 {
+                    "Feedback".to_string()
+                } else {
+                    format!("{:.0}% FB", x)
+                }
+            }),
+            auto_release_shape: Parameter::new("Auto Shape", 100.0, 0.0, 100.0, |x| {
+                format!("{:.0}%", x)
+            }),
+            hold: Parameter::new("Hold", 0.0, 0.0, 100.0, |x| format!("{:.2}ms", x)),
+            mode: Parameter::new("Mode", 0.0, 0.0, 4.0, |x| {
+                Mode::from_param(x).name().to_string()
+            }),
+            range: Parameter::new("Range", 40.0, 0.0, 100.0, |x| format!("{:.2}dB", x)),
+            hysteresis: Parameter::new("Hysteresis", 3.0, 0.0, 20.0, |x| format!("{:.2}dB", x)),
+            noise_floor: Parameter::new("Noise Floor", -70.0, -120.0, -20.0, |x| {
+                format!("{:.2}dB", x)
+            }),
+            mix: Parameter::new("Mix", 100.0, 0.0, 100.0, |x| format!("{:.0}%", x)),
+            //Whether the output gain applies to the wet signal only or the dry/wet sum
+            gain_on_sum: Parameter::new("Gain On", 0.0, 0.0, 1.0, |x| {
+                if x < 0.5 {
+                    "Wet".to_string()
+                } else {
+                    "Sum".to_string()
+                }
+            }),
+            auto_makeup: Parameter::new("Auto Makeup", 0.0, 0.0, 2.0, |x| {
+                match x.round() as i32 {
+                    0 => "Off",
+                    1 => "Curve",
+                    _ => "Measured",
+                }
+                .to_string()
+            }),
+            detector: Parameter::new("Detector", 0.0, 0.0, 2.0, |x| {
+                Detector::from_param(x).name().to_string()
+            }),
+            meters: Parameter::new("Meters", 0.0, 0.0, 1.0, |x| {
+                if x < 0.5 {
+                    "RMS".to_string()
+                } else {
+                    "True Peak".to_string()
+                }
+            }),
+            true_peak_ceiling: Parameter::new("TP Ceiling", -1.0, -12.0, 0.0, |x| {
+                format!("{:.2}dB", x)
+            }),
+            limiter: Parameter::new("Limiter", 0.0, 0.0, 1.0, |x| {
+                if x < 0.5 {
+                    "Off".to_string()
+                } else {
+                    "On".to_string()
+                }
+            }),
+            limiter_ceiling: Parameter::new("Ceiling", -0.3, -12.0, 0.0, |x| format!("{:.2}dB", x)),
+            saturation: Parameter::new("Saturation", 0.0, 0.0, 3.0, |x| {
+                Curve::from_param(x).name().to_string()
+            }),
+            drive: Parameter::new("Drive", 0.0, 0.0, 24.0, |x| format!("{:.2}dB", x)),
+            saturation_position: Parameter::new("Saturation Position", 0.0, 0.0, 1.0, |x| {
+                if x < 0.5 {
+                    "Pre Gain".to_string()
+                } else {
+                    "Post Gain".to_string()
+                }
+            }),
+            smoothing: Parameter::new("Smoothing", 20.0, 0.0, 100.0, |x| format!("{:.2}ms", x)),
+            curve_points: [
+                CurvePointParameters::new(0),
+                CurvePointParameters::new(1),
+                CurvePointParameters::new(2),
+                CurvePointParameters::new(3),
+                CurvePointParameters::new(4),
+                CurvePointParameters::new(5),
+            ],
+            knee_shape: Parameter::new("Knee Shape", 0.0, 0.0, 3.0, |x| {
+                KneeShape::from_param(x).name().to_string()
+            }),
+            midi_automation: Parameter::new("MIDI Automation", 0.0, 0.0, 1.0, |x| {
+                if x < 0.5 {
+                    "Off".to_string()
+                } else {
+                    "On".to_string()
+                }
+            }),
+            scheduled: Mutex::new(Vec::with_capacity(MAX_PARAMETER_EVENTS)),
         }
     }
 }
@@ -1,11 +1,15 @@
 use imgui::*;
 use imgui_knobs::*;
 
-use crate::units::{db_to_lin, from_range, lin_to_db, sign, ConsumerDump};
+use crate::units::{db_to_lin, from_range, lin_to_db, sign, to_range, ConsumerDump};
 use imgui_baseview::{HiDpiMode, ImguiWindow, RenderSettings, Settings};
 
+use crate::compressor::{KneeShape, Mode};
 use crate::compressor_effect_parameters::CompressorEffectParameters;
+use crate::multiband::MAX_BANDS;
 use crate::parameter::Parameter;
+use crate::transfer_curve::MAX_GAIN;
+use crate::HostLatency;
 
 use vst::editor::Editor;
 
@@ -16,7 +20,7 @@ use std::sync::{Arc, Mutex};
 use vst::util::AtomicFloat;
 
 const WINDOW_WIDTH: usize = 1024;
-const WINDOW_HEIGHT: usize = 1024;
+const WINDOW_HEIGHT: usize = 1350;
 const WINDOW_WIDTH_F: f32 = WINDOW_WIDTH as f32;
 const WINDOW_HEIGHT_F: f32 = WINDOW_HEIGHT as f32;
 
@@ -31,12 +35,28 @@ const WAVEFORM_LINES: [f32; 4] = [1.0, 1.0, 1.0, 0.2];
 const TEXT: [f32; 4] = [1.0, 1.0, 1.0, 0.75];
 const DB_LINES: [f32; 4] = [1.0, 1.0, 1.0, 0.15];
 
+//Width & height of the custom transfer curve panel
+const CURVE_PANEL_SIZE: f32 = 200.0;
+//Grab area around each of its breakpoints
+const CURVE_POINT_RADIUS: f32 = 8.0;
+
+#[derive(Clone, Copy)]
 pub struct Sample {
     pub left: f32,
     pub right: f32,
     pub left_rms: f32,
     pub right_rms: f32,
-    pub cv: f32,
+    pub cv_l: f32,
+    pub cv_r: f32,
+    pub out_left_rms: f32,
+    pub out_right_rms: f32,
+    pub band_cv: [f32; MAX_BANDS],
+    //Auto makeup applied on top of the gain parameter, dB
+    pub makeup: f32,
+    //Input L, input R, output L & output R, only measured when the meters show true peak
+    pub true_peak: [f32; 4],
+    //Lowest gain of the output limiter since the last Sample
+    pub limiter_gain: f32,
 }
 
 pub fn draw_knob(knob: &Knob, wiper_color: &ColorSet, track_color: &ColorSet) {
@@ -95,7 +115,14 @@ pub struct EditorOnlyState {
     pub sample_data: ConsumerDump<Sample>,
     pub recent_peak_l: f32,
     pub recent_peak_r: f32,
-    pub recent_peak_cv: f32,
+    pub recent_peak_cv_l: f32,
+    pub recent_peak_cv_r: f32,
+    pub recent_peak_out_l: f32,
+    pub recent_peak_out_r: f32,
+    pub recent_peak_band_cv: [f32; MAX_BANDS],
+    pub recent_peak_limiter: f32,
+    //Band whose knobs the multiband panel shows
+    pub selected_band: usize,
 }
 
 pub struct EditorState {
@@ -103,6 +130,7 @@ pub struct EditorState {
     pub editor_only: Arc<Mutex<EditorOnlyState>>,
     pub sample_rate: Arc<AtomicFloat>,
     pub time: Arc<AtomicFloat>,
+    pub latency: Arc<HostLatency>,
 }
 
 pub struct CompressorPluginEditor {
@@ -216,6 +244,21 @@ fn draw_meter(
     }
 }
 
+//Thin line across a meter at value, e.g. a ceiling
+fn draw_meter_marker(ui: &Ui, size: [f32; 2], value: f32, bottom: f32, top: f32, color: [f32; 4]) {
+    let draw_list = ui.get_window_draw_list();
+    let cursor = ui.cursor_screen_pos();
+    let pos = from_range(bottom, top, value.max(bottom).min(top));
+    draw_list
+        .add_rect(
+            [cursor[0] + size[0] * pos - 1.0, cursor[1] - 2.0],
+            [cursor[0] + size[0] * pos + 1.0, cursor[1] + size[1] + 2.0],
+            color,
+        )
+        .filled(true)
+        .build();
+}
+
 fn draw_db_lines(
     ui: &Ui,
     bottom: f32,
@@ -327,6 +370,113 @@ fn draw_meter_knob(
     }
 }
 
+//The Custom mode's transfer curve, input dB across & output dB up. Each breakpoint can be
+//dragged to a new input & output.
+fn draw_transfer_curve(ui: &Ui, params: &CompressorEffectParameters, color: [f32; 4]) {
+    let size = CURVE_PANEL_SIZE;
+    let draw_list = ui.get_window_draw_list();
+    let cursor = ui.cursor_screen_pos();
+    let bottom = params.curve_points[0].input.min;
+    let top = params.curve_points[0].input.max;
+    let to_screen = |input: f32, output: f32| {
+        [
+            cursor[0] + from_range(bottom, top, input.max(bottom).min(top)) * size,
+            cursor[1] + size - from_range(bottom, top, output.max(bottom).min(top)) * size,
+        ]
+    };
+
+    draw_list
+        .add_rect(cursor, [cursor[0] + size, cursor[1] + size], BLACK)
+        .filled(true)
+        .build();
+    for db in (bottom as i32..=top as i32).rev().step_by(12) {
+        let [x, y] = to_screen(db as f32, db as f32);
+        draw_list
+            .add_line([x, cursor[1]], [x, cursor[1] + size], DB_LINES)
+            .build();
+        draw_list
+            .add_line([cursor[0], y], [cursor[0] + size, y], DB_LINES)
+            .build();
+    }
+    draw_list
+        .add_line(
+            to_screen(bottom, bottom),
+            to_screen(top, top),
+            WAVEFORM_LINES,
+        )
+        .build();
+
+    let curve = params.transfer_curve();
+    let steps = 64;
+    let mut last = to_screen(bottom, curve.output(bottom));
+    for i in 1..=steps {
+        let input = to_range(bottom, top, i as f32 / steps as f32);
+        let point = to_screen(input, curve.output(input));
+        draw_list
+            .add_line(last, point, color)
+            .thickness(2.0)
+            .build();
+        last = point;
+    }
+
+    for (i, point) in params.curve_points.iter().enumerate() {
+        let center = to_screen(point.input.get(), point.output.get());
+        ui.set_cursor_screen_pos([
+            center[0] - CURVE_POINT_RADIUS,
+            center[1] - CURVE_POINT_RADIUS,
+        ]);
+        ui.invisible_button(
+            &ImString::new(format!("##CURVE_POINT_{}", i)),
+            [CURVE_POINT_RADIUS * 2.0, CURVE_POINT_RADIUS * 2.0],
+        );
+        if ui.is_item_active() {
+            let mouse = ui.io().mouse_pos;
+            let x = ((mouse[0] - cursor[0]) / size).max(0.0).min(1.0);
+            let y = (1.0 - (mouse[1] - cursor[1]) / size).max(0.0).min(1.0);
+            //A point can't be dragged past its neighbours, or above the curve's gain limit
+            let current = point.input.get();
+            let (mut lower, mut upper) = (bottom, top);
+            for (j, other) in params.curve_points.iter().enumerate() {
+                let other = other.input.get();
+                if j != i && other <= current {
+                    lower = lower.max(other);
+                } else if j != i {
+                    upper = upper.min(other);
+                }
+            }
+            let input = to_range(bottom, top, x).max(lower).min(upper);
+            point.input.set(input);
+            point
+                .output
+                .set(to_range(bottom, top, y).min(input + MAX_GAIN));
+        }
+        if ui.is_item_active() || ui.is_item_hovered() {
+            draw_list.add_text(
+                [
+                    center[0] + CURVE_POINT_RADIUS,
+                    center[1] - CURVE_POINT_RADIUS * 3.0,
+                ],
+                TEXT,
+                format!(
+                    "{} / {}",
+                    point.input.get_display(),
+                    point.output.get_display()
+                ),
+            );
+            draw_list
+                .add_circle(center, CURVE_POINT_RADIUS * 0.75, ORANGE_HOVERED)
+                .filled(true)
+                .build();
+        } else {
+            draw_list
+                .add_circle(center, CURVE_POINT_RADIUS * 0.5, color)
+                .filled(true)
+                .build();
+        }
+    }
+    ui.set_cursor_screen_pos([cursor[0], cursor[1] + size]);
+}
+
 fn move_cursor(ui: &Ui, x: f32, y: f32) {
     let cursor = ui.cursor_pos();
     ui.set_cursor_pos([cursor[0] + x, cursor[1] + y])
@@ -339,16 +489,21 @@ fn floating_text(ui: &Ui, text: &str) {
 
 fn draw_meters(
     ui: &Ui,
-    left: f32,
-    right: f32,
-    cv: f32,
-    recent_peak_l: f32,
-    recent_peak_r: f32,
-    recent_peak_cv: f32,
-    gain: f32,
+    current: &Sample,
+    editor_only: &EditorOnlyState,
+    gr_label: &str,
+    bands: usize,
+    ceiling: Option<f32>,
+    limiter: bool,
 ) {
     let distance_between_pairs = 30.0;
     let distance_between_meters = 15.0;
+    let band_meters_height = if bands > 1 {
+        distance_between_pairs + distance_between_meters * (bands - 1) as f32
+    } else {
+        0.0
+    };
+    let limiter_meter_height = if limiter { distance_between_pairs } else { 0.0 };
 
     let start_cursor_x = ui.cursor_pos()[0];
 
@@ -360,7 +515,10 @@ fn draw_meters(
         -39.0,
         3.0,
         1.0,
-        [WINDOW_WIDTH_F - 65.0, 200.0],
+        [
+            WINDOW_WIDTH_F - 65.0,
+            200.0 + band_meters_height + limiter_meter_height,
+        ],
         3,
         DB_LINES,
         TEXT,
@@ -375,38 +533,44 @@ fn draw_meters(
     draw_meter(
         ui,
         [WINDOW_WIDTH_F - 65.0, 4.0],
-        lin_to_db(left),
-        lin_to_db(recent_peak_l),
+        lin_to_db(current.left_rms),
+        lin_to_db(editor_only.recent_peak_l),
         -39.0,
         3.0,
         BLACK,
         GREEN,
         false,
     );
+    if let Some(ceiling) = ceiling {
+        draw_meter_marker(ui, [WINDOW_WIDTH_F - 65.0, 4.0], ceiling, -39.0, 3.0, RED);
+    }
 
     move_cursor(ui, 0.0, distance_between_meters);
 
     draw_meter(
         ui,
         [WINDOW_WIDTH_F - 65.0, 4.0],
-        lin_to_db(right),
-        lin_to_db(recent_peak_r),
+        lin_to_db(current.right_rms),
+        lin_to_db(editor_only.recent_peak_r),
         -39.0,
         3.0,
         BLACK,
         GREEN,
         false,
     );
+    if let Some(ceiling) = ceiling {
+        draw_meter_marker(ui, [WINDOW_WIDTH_F - 65.0, 4.0], ceiling, -39.0, 3.0, RED);
+    }
 
     move_cursor(ui, -45.0, distance_between_pairs);
-    floating_text(ui, "GR");
+    floating_text(ui, gr_label);
     move_cursor(ui, 45.0, 0.0);
 
     draw_meter(
         ui,
         [WINDOW_WIDTH_F - 65.0, 4.0],
-        lin_to_db(cv),
-        lin_to_db(recent_peak_cv),
+        lin_to_db(current.cv_l),
+        lin_to_db(editor_only.recent_peak_cv_l),
         -39.0,
         3.0,
         BLACK,
@@ -417,8 +581,8 @@ fn draw_meters(
     draw_meter(
         ui,
         [WINDOW_WIDTH_F - 65.0, 4.0],
-        lin_to_db(cv),
-        lin_to_db(recent_peak_cv),
+        lin_to_db(current.cv_r),
+        lin_to_db(editor_only.recent_peak_cv_r),
         -39.0,
         3.0,
         BLACK,
@@ -428,30 +592,72 @@ fn draw_meters(
     move_cursor(ui, -55.0, distance_between_pairs);
     floating_text(ui, "OUT");
     move_cursor(ui, 55.0, 0.0);
-    let gain = db_to_lin(gain);
     draw_meter(
         ui,
         [WINDOW_WIDTH_F - 65.0, 4.0],
-        lin_to_db(left * cv * gain),
-        lin_to_db(recent_peak_cv * recent_peak_l * gain),
+        lin_to_db(current.out_left_rms),
+        lin_to_db(editor_only.recent_peak_out_l),
         -39.0,
         3.0,
         BLACK,
         GREEN,
         false,
     );
+    if let Some(ceiling) = ceiling {
+        draw_meter_marker(ui, [WINDOW_WIDTH_F - 65.0, 4.0], ceiling, -39.0, 3.0, RED);
+    }
     move_cursor(ui, 0.0, distance_between_meters);
     draw_meter(
         ui,
         [WINDOW_WIDTH_F - 65.0, 4.0],
-        lin_to_db(right * cv * gain),
-        lin_to_db(recent_peak_cv * recent_peak_r * gain),
+        lin_to_db(current.out_right_rms),
+        lin_to_db(editor_only.recent_peak_out_r),
         -39.0,
         3.0,
         BLACK,
         GREEN,
         false,
     );
+    if let Some(ceiling) = ceiling {
+        draw_meter_marker(ui, [WINDOW_WIDTH_F - 65.0, 4.0], ceiling, -39.0, 3.0, RED);
+    }
+    if limiter {
+        move_cursor(ui, -55.0, distance_between_pairs);
+        floating_text(ui, "LIM");
+        move_cursor(ui, 55.0, 0.0);
+        draw_meter(
+            ui,
+            [WINDOW_WIDTH_F - 65.0, 4.0],
+            lin_to_db(current.limiter_gain),
+            lin_to_db(editor_only.recent_peak_limiter),
+            -39.0,
+            3.0,
+            BLACK,
+            RED,
+            true,
+        );
+    }
+    if bands > 1 {
+        move_cursor(ui, -65.0, distance_between_pairs);
+        floating_text(ui, "BANDS");
+        move_cursor(ui, 65.0, 0.0);
+        for band in 0..bands {
+            if band > 0 {
+                move_cursor(ui, 0.0, distance_between_meters);
+            }
+            draw_meter(
+                ui,
+                [WINDOW_WIDTH_F - 65.0, 4.0],
+                lin_to_db(current.band_cv[band]),
+                lin_to_db(editor_only.recent_peak_band_cv[band]),
+                -39.0,
+                3.0,
+                BLACK,
+                RED,
+                true,
+            );
+        }
+    }
     let cursor = ui.cursor_pos();
     ui.set_cursor_pos([start_cursor_x, cursor[1] + 160.0]);
 }
@@ -554,20 +760,38 @@ fn draw_graphs(ui: &Ui, graph_v_center: f32, graph_height: f32, state: &mut Arc<
             .build();
     }
 
-    let col = ui.push_style_color(StyleColor::PlotLines, RED);
-    let col2 = ui.push_style_color(StyleColor::PlotLinesHovered, RED);
     ui.set_cursor_pos(init_cursor);
     move_cursor(ui, 0.0, 20.0);
-    draw_graph(
-        ui,
-        im_str!("Graph"),
-        [WINDOW_WIDTH_F, graph_height],
-        -128.0,
-        -256.0 + 129.0,
-        3.0,
-        sample_data.len(),
-        |i| sample_data[i].cv,
-    );
+    let (col, col2) = if Mode::from_param(state.params.mode.get()) == Mode::Upward {
+        //Boost rises from the bottom of the reduction's range as positive gain
+        let col = ui.push_style_color(StyleColor::PlotLines, GREEN);
+        let col2 = ui.push_style_color(StyleColor::PlotLinesHovered, GREEN);
+        draw_graph(
+            ui,
+            im_str!("Graph"),
+            [WINDOW_WIDTH_F, graph_height],
+            128.0,
+            -256.0 + 1.0,
+            3.0,
+            sample_data.len(),
+            |i| 1.0 / sample_data[i].cv_l.max(sample_data[i].cv_r),
+        );
+        (col, col2)
+    } else {
+        let col = ui.push_style_color(StyleColor::PlotLines, RED);
+        let col2 = ui.push_style_color(StyleColor::PlotLinesHovered, RED);
+        draw_graph(
+            ui,
+            im_str!("Graph"),
+            [WINDOW_WIDTH_F, graph_height],
+            -128.0,
+            -256.0 + 129.0,
+            3.0,
+            sample_data.len(),
+            |i| sample_data[i].cv_l.min(sample_data[i].cv_r),
+        );
+        (col, col2)
+    };
     col.pop(ui);
     col2.pop(ui);
     ui.set_cursor_pos(init_cursor);
@@ -660,17 +884,55 @@ impl Editor for CompressorPluginEditor {
                     let mut editor_only = state.editor_only.lock().unwrap();
 
                     let last = editor_only.sample_data.data.len() - 1;
-                    let left = editor_only.sample_data.data[last].left_rms;
-                    let right = editor_only.sample_data.data[last].right_rms;
-                    let cv = editor_only.sample_data.data[last].cv;
+                    let mut current = editor_only.sample_data.data[last];
+                    //The IN & OUT meters show true peak instead of RMS
+                    let true_peak = state.params.meters.get() >= 0.5;
+                    if true_peak {
+                        current.left_rms = current.true_peak[0];
+                        current.right_rms = current.true_peak[1];
+                        current.out_left_rms = current.true_peak[2];
+                        current.out_right_rms = current.true_peak[3];
+                    }
+                    //Upward compression boosts, the GR meters show it the same way as reduction
+                    let upward = Mode::from_param(state.params.mode.get()) == Mode::Upward;
+                    if upward {
+                        current.cv_l = 1.0 / current.cv_l;
+                        current.cv_r = 1.0 / current.cv_r;
+                        for cv in current.band_cv.iter_mut() {
+                            *cv = 1.0 / *cv;
+                        }
+                    }
+                    let left = current.left_rms;
+                    let right = current.right_rms;
                     if (state.time.get() * 10.0) as u32 % 10 == 0 {
                         editor_only.recent_peak_l = left;
                         editor_only.recent_peak_r = right;
-                        editor_only.recent_peak_cv = cv;
+                        editor_only.recent_peak_cv_l = current.cv_l;
+                        editor_only.recent_peak_cv_r = current.cv_r;
+                        editor_only.recent_peak_out_l = current.out_left_rms;
+                        editor_only.recent_peak_out_r = current.out_right_rms;
+                        editor_only.recent_peak_band_cv = current.band_cv;
+                        editor_only.recent_peak_limiter = current.limiter_gain;
                     } else {
                         editor_only.recent_peak_l = editor_only.recent_peak_l.max(left);
                         editor_only.recent_peak_r = editor_only.recent_peak_r.max(right);
-                        editor_only.recent_peak_cv = editor_only.recent_peak_cv.min(cv);
+                        editor_only.recent_peak_cv_l =
+                            editor_only.recent_peak_cv_l.min(current.cv_l);
+                        editor_only.recent_peak_cv_r =
+                            editor_only.recent_peak_cv_r.min(current.cv_r);
+                        editor_only.recent_peak_out_l =
+                            editor_only.recent_peak_out_l.max(current.out_left_rms);
+                        editor_only.recent_peak_out_r =
+                            editor_only.recent_peak_out_r.max(current.out_right_rms);
+                        editor_only.recent_peak_limiter =
+                            editor_only.recent_peak_limiter.min(current.limiter_gain);
+                        for (peak, cv) in editor_only
+                            .recent_peak_band_cv
+                            .iter_mut()
+                            .zip(current.band_cv.iter())
+                        {
+                            *peak = peak.min(*cv);
+                        }
                     }
 
                     let highlight = ColorSet::new(ORANGE, ORANGE_HOVERED, ORANGE_HOVERED);
@@ -704,6 +966,13 @@ impl Editor for CompressorPluginEditor {
                     ui.next_column();
 
                     make_knob(ui, &params.knee, &highlight, &lowlight, 0.0);
+                    if KneeShape::from_param(params.knee_shape.get()) != KneeShape::Quadratic {
+                        knob_title(
+                            ui,
+                            &ImString::new(params.knee_shape.get_display().to_uppercase()),
+                            line_height * 4.75,
+                        );
+                    }
                     ui.next_column();
 
                     //make_knob(ui, &params.pre_smooth, &highlight, &lowlight);
@@ -722,22 +991,130 @@ impl Editor for CompressorPluginEditor {
                     ui.next_column();
 
                     make_knob(ui, &params.gain, &highlight, &lowlight, 0.0);
+                    if params.auto_makeup.get() >= 0.5 {
+                        knob_title(
+                            ui,
+                            &ImString::new(format!("AUTO {:+.2}dB", current.makeup)),
+                            line_height * 4.75,
+                        );
+                    }
                     ui.next_column();
 
                     ui.columns(1, im_str!("nocols"), false);
 
                     move_cursor(ui, 0.0, 84.0);
+                    //The GR meters show mid & side reduction in M/S mode
+                    let gr_label = if upward {
+                        "UP"
+                    } else if params.mid_side.get() >= 0.5 {
+                        "M/S"
+                    } else {
+                        "GR"
+                    };
+                    let bands = (params.band_count.get().round() as usize).clamp(1, MAX_BANDS);
+                    let ceiling = if true_peak {
+                        Some(params.true_peak_ceiling.get())
+                    } else {
+                        None
+                    };
+                    let limiter = params.limiter.get() >= 0.5;
                     draw_meters(
                         ui,
-                        left,
-                        right,
-                        cv,
-                        editor_only.recent_peak_l,
-                        editor_only.recent_peak_r,
-                        editor_only.recent_peak_cv,
-                        params.gain.get(),
+                        &current,
+                        &editor_only,
+                        gr_label,
+                        bands,
+                        ceiling,
+                        limiter,
                     );
 
+                    ui.columns(7, im_str!("multiband_cols"), false);
+                    for i in 1..7 {
+                        ui.set_column_width(i, width);
+                    }
+                    ui.set_column_width(0, width * 0.5);
+
+                    ui.next_column();
+                    make_knob(ui, &params.band_count, &highlight, &lowlight, 0.0);
+                    ui.next_column();
+
+                    //Only show the crossovers that are in use
+                    let crossovers = [
+                        &params.crossover_1,
+                        &params.crossover_2,
+                        &params.crossover_3,
+                    ];
+                    for crossover in crossovers.iter().take(bands - 1) {
+                        make_knob(ui, crossover, &highlight, &lowlight, 0.0);
+                        ui.next_column();
+                    }
+
+                    //Mix sits under the gain knob
+                    for _ in bands + 1..6 {
+                        ui.next_column();
+                    }
+                    make_knob(ui, &params.mix, &highlight, &lowlight, 0.0);
+                    ui.next_column();
+
+                    ui.columns(1, im_str!("multiband_nocols"), false);
+
+                    if bands > 1 {
+                        ui.columns(7, im_str!("band_cols"), false);
+                        for i in 1..7 {
+                            ui.set_column_width(i, width);
+                        }
+                        ui.set_column_width(0, width * 0.5);
+
+                        //One band at a time, its knobs sit under the main ones they replace
+                        editor_only.selected_band = editor_only.selected_band.min(bands - 1);
+                        for band in 0..bands {
+                            let color = if band == editor_only.selected_band {
+                                ORANGE
+                            } else {
+                                BLACK
+                            };
+                            let button_color = ui.push_style_color(StyleColor::Button, color);
+                            let hovered_color =
+                                ui.push_style_color(StyleColor::ButtonHovered, ORANGE_HOVERED);
+                            if ui.button(
+                                &ImString::new(format!("B{}", band + 1)),
+                                [width * 0.35, line_height * 1.5],
+                            ) {
+                                editor_only.selected_band = band;
+                            }
+                            hovered_color.pop(ui);
+                            button_color.pop(ui);
+                        }
+                        ui.next_column();
+
+                        let band_params = &params.bands[editor_only.selected_band];
+                        make_knob(ui, &band_params.threshold, &highlight, &lowlight, 0.0);
+                        ui.next_column();
+
+                        //Bands share the knee, bypass takes its place
+                        make_knob(ui, &band_params.bypass, &highlight, &lowlight, 0.0);
+                        ui.next_column();
+
+                        make_knob(ui, &band_params.ratio, &highlight, &lowlight, 0.0);
+                        ui.next_column();
+
+                        make_knob(ui, &band_params.attack, &highlight, &lowlight, 0.0);
+                        ui.next_column();
+
+                        make_knob(ui, &band_params.release, &highlight, &lowlight, 0.0);
+                        ui.next_column();
+
+                        make_knob(ui, &band_params.gain, &highlight, &lowlight, 0.0);
+                        ui.next_column();
+
+                        ui.columns(1, im_str!("band_nocols"), false);
+                    }
+
+                    if Mode::from_param(params.mode.get()) == Mode::Custom {
+                        move_cursor(ui, width * 0.5, 20.0);
+                        draw_transfer_curve(ui, params, ORANGE);
+                    }
+
                     text_style_color.pop(ui);
                 });
             },
@@ -753,6 +1130,11 @@ impl Editor for CompressorPluginEditor {
     fn close(&mut self) {
         self.is_open = false;
     }
+
+    //Latency changes made while the editor is closed are reported on the next resume
+    fn idle(&mut self) {
+        self.state.latency.report();
+    }
 }
 
 struct VstParent(*mut ::std::ffi::c_void);
@@ -0,0 +1,103 @@
+use crate::low_pass_filter::Biquad;
+use crate::processor::Processor;
+use crate::units::{db_to_lin, Float};
+
+//ITU-R BS.1770 stage 1, a high shelf for the acoustic effect of the head
+const SHELF_FREQ: f64 = 1681.974450955533;
+const SHELF_GAIN: f64 = 3.999843853973347;
+const SHELF_Q: f64 = 0.7071752369554196;
+//Share of the shelf's gain at the band edge
+const SHELF_BAND_EXPONENT: f64 = 0.4996667741545416;
+//ITU-R BS.1770 stage 2, the revised low-frequency B (RLB) high pass
+const HIGH_PASS_FREQ: f64 = 38.13547087602444;
+const HIGH_PASS_Q: f64 = 0.5003270373238773;
+//BS.1770's -0.691dB, brings the filter's gain at 1khz back to 0dB
+const OFFSET: f64 = -0.691;
+
+//K-weighting, the loudness pre-filter of ITU-R BS.1770. The standard's coefficients are for
+//48khz, here they're redesigned from their analog prototypes for any sample rate.
+pub struct KWeighting<T: Float> {
+    shelf: Biquad<T>,
+    high_pass: Biquad<T>,
+    gain: T,
+}
+
+impl<T: Float> KWeighting<T> {
+    pub fn new(sample_rate: f32) -> KWeighting<T> {
+        let mut filter = KWeighting {
+            shelf: Biquad::new(),
+            high_pass: Biquad::new(),
+            gain: db_to_lin(T::from_f64(OFFSET)),
+        };
+        filter.set_sample_rate(sample_rate);
+        filter
+    }
+
+    pub fn set_sample_rate(&mut self, sample_rate: f32) {
+        let one = T::from_f64(1.0);
+        let two = T::from_f64(2.0);
+        let rate = T::from_f32(sample_rate);
+
+        let k = (T::PI * T::from_f64(SHELF_FREQ) / rate).tan();
+        let q = T::from_f64(SHELF_Q);
+        let vh = T::from_f64(10.0f64.powf(SHELF_GAIN / 20.0));
+        let vb = vh.powf(T::from_f64(SHELF_BAND_EXPONENT));
+        self.shelf.set_coefficients(
+            [
+                vh + vb * k / q + k * k,
+                two * (k * k - vh),
+                vh - vb * k / q + k * k,
+            ],
+            [
+                one + k / q + k * k,
+                two * (k * k - one),
+                one - k / q + k * k,
+            ],
+        );
+
+        let k = (T::PI * T::from_f64(HIGH_PASS_FREQ) / rate).tan();
+        let q = T::from_f64(HIGH_PASS_Q);
+        //The standard leaves the numerator at 1, -2, 1 rather than normalizing it
+        let a0 = one + k / q + k * k;
+        self.high_pass.set_coefficients(
+            [a0, -two * a0, a0],
+            [a0, two * (k * k - one), one - k / q + k * k],
+        );
+    }
+
+    pub fn reset(&mut self) {
+        self.shelf.reset();
+        self.high_pass.reset();
+    }
+
+    pub fn process(&mut self, x: T) -> T {
+        self.high_pass.process(self.shelf.process(x * self.gain))
+    }
+
+    pub fn process_block(&mut self, buffer: &mut [T]) {
+        let gain = self.gain;
+        for x in buffer.iter_mut() {
+            *x *= gain;
+        }
+        self.shelf.process_block(buffer);
+        self.high_pass.process_block(buffer);
+    }
+}
+
+impl<T: Float> Processor<T> for KWeighting<T> {
+    fn reset(&mut self) {
+        KWeighting::reset(self);
+    }
+
+    fn set_sample_rate(&mut self, sample_rate: f32) {
+        KWeighting::set_sample_rate(self, sample_rate);
+    }
+
+    fn process(&mut self, x: T) -> T {
+        KWeighting::process(self, x)
+    }
+
+    fn process_block(&mut self, buffer: &mut [T]) {
+        KWeighting::process_block(self, buffer);
+    }
+}
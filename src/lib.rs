@@ -4,21 +4,48 @@
 extern crate vst;
 
 pub mod compressor;
-mod compressor_effect_parameters;
+pub mod compressor_effect_parameters;
 mod editor;
+pub mod k_weighting;
+pub mod limiter;
 pub mod low_pass_filter;
+pub mod multiband;
+pub mod oversampling;
 mod parameter;
+pub mod processor;
+pub mod saturation;
+pub mod sidechain_filter;
+pub mod stereo;
+pub mod transfer_curve;
+pub mod true_peak;
 pub mod units;
 
-use compressor::Compressor;
-use compressor_effect_parameters::CompressorEffectParameters;
+use compressor::{Detector, KneeShape, Mode};
+use compressor_effect_parameters::{
+    CompressorEffectParameters, ParameterEvent, MAX_PARAMETER_EVENTS,
+};
 use editor::{CompressorPluginEditor, EditorOnlyState, EditorState};
-use units::{db_to_lin, ConsumerDump};
+use k_weighting::KWeighting;
+use limiter::Limiter;
+use multiband::{MultibandCompressor, MAX_BANDS};
+use oversampling::{Downsampler, Upsampler, MAX_FACTOR};
+use parameter::Smoother;
+use processor::{Bypass, Chain, Gain, Processor};
+use saturation::{Curve, Saturator, SATURATION_LATENCY};
+use sidechain_filter::SidechainFilter;
+use stereo::{StereoCompressor, StereoLink};
+use true_peak::{TruePeakDetector, TRUE_PEAK_LATENCY};
+use units::{db_to_lin, ConsumerDump, DelayLine, Float};
 
+use vst::api::Events;
 use vst::buffer::AudioBuffer;
+use vst::channels::ChannelInfo;
 use vst::editor::Editor;
-use vst::plugin::{Category, Info, Plugin, PluginParameters};
+use vst::event::Event;
+use vst::host::OpCode;
+use vst::plugin::{CanDo, Category, HostCallback, Info, Plugin, PluginParameters, Supported};
 
+use std::sync::atomic::{AtomicUsize, Ordering};
 use std::sync::{Arc, Mutex};
 
 use ringbuf::{Producer, RingBuffer};
@@ -26,25 +53,190 @@ use ringbuf::{Producer, RingBuffer};
 use vst::util::AtomicFloat;
 
 const DATA_SIZE: usize = 3000;
+//20ms at 384khz, plus the true peak detector's delay
+const MAX_LOOKAHEAD_SAMPLES: usize = 7680 + TRUE_PEAK_LATENCY;
+//The most the dry signal waits for the dynamics: lookahead, oversampling & pre saturation
+const MAX_DRY_DELAY: usize =
+    MAX_LOOKAHEAD_SAMPLES + oversampling::FILTER_LENGTH + SATURATION_LATENCY;
+//The measured auto makeup averages the gain reduction over 3s, in 10ms steps
+const MAKEUP_RATE: u32 = 100;
+const MAKEUP_WINDOW: usize = 300;
+//Samples each stage runs over before the next, sized for the stack buffers
+const BLOCK_SIZE: usize = 64;
+//MIDI status byte, without the channel
+const CONTROL_CHANGE: u8 = 0xB0;
+//Controllers 120 and up are channel mode messages like all notes off, never parameters
+const CHANNEL_MODE: u8 = 120;
+
+//Picks the plugin's DSP state for a sample type, along with the meter sample producer
+trait Precision: Float {
+    fn dsp(plugin: &mut CompressorPlugin) -> (&mut Dsp<Self>, &mut Producer<editor::Sample>);
+}
+
+impl Precision for f32 {
+    fn dsp(plugin: &mut CompressorPlugin) -> (&mut Dsp<f32>, &mut Producer<editor::Sample>) {
+        (&mut plugin.dsp, &mut plugin.sample_producer)
+    }
+}
+
+impl Precision for f64 {
+    fn dsp(plugin: &mut CompressorPlugin) -> (&mut Dsp<f64>, &mut Producer<editor::Sample>) {
+        (&mut plugin.dsp_f64, &mut plugin.sample_producer)
+    }
+}
+
+//The latency the audio thread last computed and the one the host was told about. Some hosts
+//don't allow IOChanged from the audio thread, so report is called from the host's threads:
+//resume, set_sample_rate and the editor's idle.
+pub struct HostLatency {
+    host: HostCallback,
+    latency: AtomicUsize,
+    reported: AtomicUsize,
+}
+
+impl HostLatency {
+    fn new(host: HostCallback) -> HostLatency {
+        HostLatency {
+            host,
+            latency: AtomicUsize::new(0),
+            reported: AtomicUsize::new(0),
+        }
+    }
+
+    fn set(&self, latency: usize) {
+        self.latency.store(latency, Ordering::Relaxed);
+    }
+
+    fn reported(&self) -> usize {
+        self.reported.load(Ordering::Relaxed)
+    }
+
+    //Tells the host about a new initial delay, so it can re-align the plugin's output
+    fn report(&self) {
+        let latency = self.latency.load(Ordering::Relaxed);
+        if self.reported.swap(latency, Ordering::Relaxed) == latency {
+            return;
+        }
+        let effect = self.host.raw_effect();
+        if let Some(callback) = self.host.raw_callback() {
+            if !effect.is_null() {
+                unsafe {
+                    (*effect).initialDelay = latency as i32;
+                }
+                callback(
+                    effect,
+                    OpCode::IOChanged as i32,
+                    0,
+                    0,
+                    std::ptr::null_mut(),
+                    0.0,
+                );
+            }
+        }
+    }
+}
 
 struct CompressorPlugin {
+    host: HostCallback,
     params: Arc<CompressorEffectParameters>,
     editor: Option<CompressorPluginEditor>,
     time: Arc<AtomicFloat>,
     sample_rate: Arc<AtomicFloat>,
-    compressor: Compressor,
+    //State for process & process_f64, a host only ever calls one of them
+    dsp: Dsp<f32>,
+    dsp_f64: Dsp<f64>,
     sample_producer: Producer<editor::Sample>,
-    cv_lpf: low_pass_filter::LowPassFilter,
-    amplitude_lpf_l: low_pass_filter::LowPassFilter,
-    amplitude_lpf_r: low_pass_filter::LowPassFilter,
-    amplitude_rms_l: units::AccumulatingRMS,
-    amplitude_rms_r: units::AccumulatingRMS,
+    latency: Arc<HostLatency>,
+    parameter_events: Vec<ParameterEvent>,
+}
+
+//The sidechain filter, then K-weighting or the true peak detector depending on the detector
+type KeyPath<T> =
+    Chain<Chain<SidechainFilter<T>, Bypass<KWeighting<T>>>, Bypass<TruePeakDetector<T>>>;
+
+fn key_path<T: Float>() -> KeyPath<T> {
+    SidechainFilter::new(44100.0)
+        .then(Bypass::new(KWeighting::new(44100.0), true))
+        .then(Bypass::new(TruePeakDetector::new(), true))
+}
+
+//What the compressed signal goes through before the mix: pre saturation, the auto makeup,
+//then the output gain unless it's on the dry/wet sum
+type WetPath<T> = Chain<Chain<Bypass<Saturator<T>>, Gain<T>>, Gain<T>>;
+
+fn wet_path<T: Float>() -> WetPath<T> {
+    Bypass::new(Saturator::new(44100.0), true)
+        .then(Gain::new())
+        .then(Gain::new())
+}
+
+//After the mix: the output gain if it's on the dry/wet sum, then post saturation
+type OutputStage<T> = Chain<Gain<T>, Bypass<Saturator<T>>>;
+
+fn output_stage<T: Float>() -> OutputStage<T> {
+    Gain::new().then(Bypass::new(Saturator::new(44100.0), true))
+}
+
+//Everything that processes or meters the audio, in the host's sample type
+struct Dsp<T: Float> {
+    params: Arc<CompressorEffectParameters>,
+    sample_rate: Arc<AtomicFloat>,
+    stereo_compressor: StereoCompressor<T>,
+    multiband: MultibandCompressor<T>,
+    cv_lpf_l: low_pass_filter::LowPassFilter<T>,
+    cv_lpf_r: low_pass_filter::LowPassFilter<T>,
+    amplitude_lpf_l: low_pass_filter::LowPassFilter<T>,
+    amplitude_lpf_r: low_pass_filter::LowPassFilter<T>,
+    amplitude_rms_l: units::AccumulatingRMS<T>,
+    amplitude_rms_r: units::AccumulatingRMS<T>,
+    amplitude_rms_out_l: units::AccumulatingRMS<T>,
+    amplitude_rms_out_r: units::AccumulatingRMS<T>,
+    key_path_l: KeyPath<T>,
+    key_path_r: KeyPath<T>,
+    //Input L, input R, output L & output R, with the highest peak since the last Sample
+    meter_true_peaks: [TruePeakDetector<T>; 4],
+    meter_true_peak_max: [f32; 4],
+    lookahead_l: DelayLine<T>,
+    lookahead_r: DelayLine<T>,
+    dry_delay_l: DelayLine<T>,
+    dry_delay_r: DelayLine<T>,
+    mix_smoother: Smoother<T>,
+    wet_path_l: WetPath<T>,
+    wet_path_r: WetPath<T>,
+    output_stage_l: OutputStage<T>,
+    output_stage_r: OutputStage<T>,
+    saturation: Curve,
+    true_peak_meters: bool,
+    //Auto makeup in dB
+    makeup: f32,
+    limiter: Limiter<T>,
+    limiter_enabled: bool,
+    //Lowest limiter gain since the last Sample
+    limiter_gain_min: f32,
+    oversampling: usize,
+    upsampler_l: Upsampler<T>,
+    upsampler_r: Upsampler<T>,
+    key_upsampler_l: Upsampler<T>,
+    key_upsampler_r: Upsampler<T>,
+    downsampler_l: Downsampler<T>,
+    downsampler_r: Downsampler<T>,
+    makeup_average: units::MovingAverage<f32>,
+    measured_makeup: f32,
+    makeup_i: u32,
+    //Energy into & out of the dynamics over the current makeup step, both channels summed
+    makeup_energy: (T, T),
     data_i: u32,
-    block_size: i64,
 }
 
 impl Default for CompressorPlugin {
     fn default() -> Self {
+        CompressorPlugin::with_host(HostCallback::default())
+    }
+}
+
+impl CompressorPlugin {
+    fn with_host(host: HostCallback) -> Self {
+        let latency = Arc::new(HostLatency::new(host));
         let params = Arc::new(CompressorEffectParameters::default());
         let time = Arc::new(AtomicFloat::new(0.0));
         let sample_rate = Arc::new(AtomicFloat::new(44100.0));
@@ -52,9 +244,9 @@ impl Default for CompressorPlugin {
         let sample_ring = RingBuffer::<editor::Sample>::new(DATA_SIZE);
         let (sample_producer, sample_consumer) = sample_ring.split();
         Self {
+            host,
             params: params.clone(),
             sample_rate: sample_rate.clone(),
-            block_size: 128,
             sample_producer,
             time: time.clone(),
             editor: Some(CompressorPluginEditor {
@@ -63,23 +255,687 @@ impl Default for CompressorPlugin {
                     params: params.clone(),
                     sample_rate: sample_rate.clone(),
                     time: time.clone(),
+                    latency: latency.clone(),
                     editor_only: Arc::new(Mutex::new(EditorOnlyState {
                         sample_data: ConsumerDump::new(sample_consumer, DATA_SIZE),
                         recent_peak_l: 0.0,
                         recent_peak_r: 0.0,
-                        recent_peak_cv: 0.0,
+                        recent_peak_cv_l: 0.0,
+                        recent_peak_cv_r: 0.0,
+                        recent_peak_out_l: 0.0,
+                        recent_peak_out_r: 0.0,
+                        recent_peak_band_cv: [1.0; MAX_BANDS],
+                        recent_peak_limiter: 1.0,
+                        selected_band: 0,
                     })),
                 }),
             }),
-            compressor: Compressor::new(),
-            cv_lpf: low_pass_filter::LowPassFilter::new(50.0, 0.2, 44100.0),
+            dsp: Dsp::new(params.clone(), sample_rate.clone()),
+            dsp_f64: Dsp::new(params, sample_rate),
+            latency,
+            parameter_events: Vec::with_capacity(MAX_PARAMETER_EVENTS),
+        }
+    }
+
+    fn is_offline(&self) -> bool {
+        const PROCESS_LEVEL_OFFLINE: isize = 4;
+        let effect = self.host.raw_effect();
+        match self.host.raw_callback() {
+            Some(callback) if !effect.is_null() => {
+                callback(
+                    effect,
+                    OpCode::GetCurrentProcessLevel as i32,
+                    0,
+                    0,
+                    std::ptr::null_mut(),
+                    0.0,
+                ) == PROCESS_LEVEL_OFFLINE
+            }
+            _ => false,
+        }
+    }
+
+    //Works out the latency from the host's thread while the plugin isn't processing, and
+    //reports it if it changed
+    fn update_latency(&mut self) {
+        let offline = self.is_offline();
+        self.dsp.update_latency(offline);
+        self.dsp_f64.update_latency(offline);
+        self.latency.set(self.dsp.latency());
+        self.latency.report();
+    }
+
+    //Queues a change for the next block after any already queued for the same offset, so the
+    //last one wins. Returns false if the queue is full.
+    fn queue_event(&mut self, event: ParameterEvent) -> bool {
+        if self.parameter_events.len() >= MAX_PARAMETER_EVENTS {
+            return false;
+        }
+        let position = self
+            .parameter_events
+            .partition_point(|queued| queued.offset <= event.offset);
+        self.parameter_events.insert(position, event);
+        true
+    }
+
+    //Splits the block wherever a scheduled or MIDI parameter change lands so it takes effect on
+    //its sample. Scheduled changes that can't be taken this block, because the queue is being
+    //written to or is full, wait for the next one.
+    fn process_block<T: Precision>(
+        &mut self,
+        inputs: (&[T], &[T]),
+        sidechain: (&[T], &[T]),
+        outputs: (&mut [T], &mut [T]),
+    ) {
+        let samples = inputs.0.len();
+        let (inputs_left, inputs_right) = inputs;
+        let (keys_left, keys_right) = sidechain;
+        let (outputs_left, outputs_right) = outputs;
+        //Asks the host once per block rather than once per segment
+        let offline = self.is_offline();
+        let params = self.params.clone();
+        if let Ok(mut scheduled) = params.scheduled.try_lock() {
+            let taken = scheduled
+                .len()
+                .min(MAX_PARAMETER_EVENTS - self.parameter_events.len());
+            for event in scheduled.drain(..taken) {
+                self.queue_event(event);
+            }
+        }
+        let mut start = 0;
+        for i in 0..self.parameter_events.len() {
+            let event = self.parameter_events[i];
+            let offset = event.offset.max(start).min(samples);
+            if offset > start {
+                self.process_segment(
+                    offline,
+                    (&inputs_left[start..offset], &inputs_right[start..offset]),
+                    (&keys_left[start..offset], &keys_right[start..offset]),
+                    (
+                        &mut outputs_left[start..offset],
+                        &mut outputs_right[start..offset],
+                    ),
+                );
+                start = offset;
+            }
+            self.params[event.index].set_normalized(event.value);
+        }
+        self.parameter_events.clear();
+        if start < samples {
+            self.process_segment(
+                offline,
+                (&inputs_left[start..], &inputs_right[start..]),
+                (&keys_left[start..], &keys_right[start..]),
+                (&mut outputs_left[start..], &mut outputs_right[start..]),
+            );
+        }
+    }
+
+    //Processes a stretch of the block over which the parameters don't change
+    fn process_segment<T: Precision>(
+        &mut self,
+        offline: bool,
+        inputs: (&[T], &[T]),
+        sidechain: (&[T], &[T]),
+        outputs: (&mut [T], &mut [T]),
+    ) {
+        let (dsp, _) = T::dsp(self);
+        dsp.update_latency(offline);
+        //Reported to the host from its own thread, see HostLatency
+        let latency = dsp.latency();
+        self.latency.set(latency);
+
+        self.time
+            .set(self.time.get() + (1.0 / self.sample_rate.get()) * inputs.0.len() as f32);
+
+        let (dsp, sample_producer) = T::dsp(self);
+        dsp.process(inputs, sidechain, outputs, sample_producer);
+    }
+}
+
+impl<T: Float> Dsp<T> {
+    fn new(params: Arc<CompressorEffectParameters>, sample_rate: Arc<AtomicFloat>) -> Dsp<T> {
+        Dsp {
+            params,
+            sample_rate,
+            stereo_compressor: StereoCompressor::new(),
+            multiband: MultibandCompressor::new([120.0, 1000.0, 5000.0], 44100.0),
+            cv_lpf_l: low_pass_filter::LowPassFilter::new(50.0, 0.2, 44100.0),
+            cv_lpf_r: low_pass_filter::LowPassFilter::new(50.0, 0.2, 44100.0),
             amplitude_lpf_l: low_pass_filter::LowPassFilter::new(50.0, 0.2, 44100.0),
             amplitude_lpf_r: low_pass_filter::LowPassFilter::new(50.0, 0.2, 44100.0),
             amplitude_rms_l: units::AccumulatingRMS::new(44100, 5.0, 192000),
             amplitude_rms_r: units::AccumulatingRMS::new(44100, 5.0, 192000),
+            amplitude_rms_out_l: units::AccumulatingRMS::new(44100, 5.0, 192000),
+            amplitude_rms_out_r: units::AccumulatingRMS::new(44100, 5.0, 192000),
+            key_path_l: key_path(),
+            key_path_r: key_path(),
+            meter_true_peaks: [
+                TruePeakDetector::new(),
+                TruePeakDetector::new(),
+                TruePeakDetector::new(),
+                TruePeakDetector::new(),
+            ],
+            meter_true_peak_max: [0.0; 4],
+            lookahead_l: DelayLine::new(0, MAX_LOOKAHEAD_SAMPLES),
+            lookahead_r: DelayLine::new(0, MAX_LOOKAHEAD_SAMPLES),
+            dry_delay_l: DelayLine::new(0, MAX_DRY_DELAY),
+            dry_delay_r: DelayLine::new(0, MAX_DRY_DELAY),
+            mix_smoother: Smoother::new(T::from_f64(1.0)),
+            wet_path_l: wet_path(),
+            wet_path_r: wet_path(),
+            output_stage_l: output_stage(),
+            output_stage_r: output_stage(),
+            saturation: Curve::Off,
+            true_peak_meters: false,
+            makeup: 0.0,
+            limiter: Limiter::new(44100.0),
+            limiter_enabled: false,
+            limiter_gain_min: 1.0,
+            oversampling: 1,
+            upsampler_l: Upsampler::new(1),
+            upsampler_r: Upsampler::new(1),
+            key_upsampler_l: Upsampler::new(1),
+            key_upsampler_r: Upsampler::new(1),
+            downsampler_l: Downsampler::new(1),
+            downsampler_r: Downsampler::new(1),
+            makeup_average: units::MovingAverage::new(MAKEUP_WINDOW),
+            measured_makeup: 0.0,
+            makeup_i: 0,
+            makeup_energy: (T::default(), T::default()),
             data_i: 0,
         }
     }
+
+    fn set_sample_rate(&mut self, rate: f32) {
+        self.cv_lpf_l.set_sample_rate(rate);
+        self.cv_lpf_r.set_sample_rate(rate);
+        self.amplitude_lpf_l.set_sample_rate(rate);
+        self.amplitude_lpf_r.set_sample_rate(rate);
+        self.key_path_l.set_sample_rate(rate);
+        self.key_path_r.set_sample_rate(rate);
+        self.limiter.set_sample_rate(rate);
+        self.wet_path_l.set_sample_rate(rate);
+        self.wet_path_r.set_sample_rate(rate);
+        self.output_stage_l.set_sample_rate(rate);
+        self.output_stage_r.set_sample_rate(rate);
+        self.amplitude_rms_l
+            .resize(rate as usize, self.params.rms.get());
+        self.amplitude_rms_r
+            .resize(rate as usize, self.params.rms.get());
+        self.amplitude_rms_out_l
+            .resize(rate as usize, self.params.rms.get());
+        self.amplitude_rms_out_r
+            .resize(rate as usize, self.params.rms.get());
+    }
+
+    //Delay of the wet signal where it's mixed with the dry signal, in samples
+    fn dynamics_latency(&self) -> usize {
+        let oversampling_latency = if self.oversampling > 1 {
+            oversampling::FILTER_LENGTH
+        } else {
+            0
+        };
+        self.lookahead_l.delay() + oversampling_latency + self.wet_path_l.latency()
+    }
+
+    //Total delay the plugin adds to the audio path, in samples
+    fn latency(&self) -> usize {
+        let limiter_latency = if self.limiter_enabled {
+            self.limiter.latency()
+        } else {
+            0
+        };
+        self.dynamics_latency() + self.output_stage_l.latency() + limiter_latency
+    }
+
+    fn set_oversampling(&mut self, factor: usize) {
+        if factor != self.oversampling {
+            self.oversampling = factor;
+            self.upsampler_l.set_factor(factor);
+            self.upsampler_r.set_factor(factor);
+            self.key_upsampler_l.set_factor(factor);
+            self.key_upsampler_r.set_factor(factor);
+            self.downsampler_l.set_factor(factor);
+            self.downsampler_r.set_factor(factor);
+        }
+    }
+
+    //Applies the parameters that change the latency
+    fn update_latency(&mut self, offline: bool) {
+        let oversampling = if offline {
+            &self.params.offline_oversampling
+        } else {
+            &self.params.oversampling
+        };
+        self.set_oversampling(oversampling::factor_from_param(oversampling.get()));
+
+        let lookahead =
+            (self.params.lookahead.get() * 0.001 * self.sample_rate.get()).round() as usize;
+        //The true peak detector lags the key, the audio is delayed by the same amount
+        let detector = Detector::from_param(self.params.detector.get());
+        for key_path in [&mut self.key_path_l, &mut self.key_path_r].iter_mut() {
+            key_path
+                .first
+                .second
+                .set_bypassed(detector != Detector::KWeighted);
+            key_path.second.set_bypassed(detector != Detector::TruePeak);
+        }
+        let key_latency = self.key_path_l.latency();
+        self.lookahead_l.set_delay(lookahead + key_latency);
+        self.lookahead_r.set_delay(lookahead + key_latency);
+
+        let limiter_enabled = self.params.limiter.get() >= 0.5;
+        if limiter_enabled != self.limiter_enabled {
+            self.limiter_enabled = limiter_enabled;
+            self.limiter.reset();
+        }
+
+        self.saturation = Curve::from_param(self.params.saturation.get());
+        let saturate = self.saturation != Curve::Off;
+        let saturation_pre = self.params.saturation_position.get() < 0.5;
+        let (saturate_pre, saturate_post) =
+            (saturate && saturation_pre, saturate && !saturation_pre);
+        for (wet_path, output_stage) in [
+            (&mut self.wet_path_l, &mut self.output_stage_l),
+            (&mut self.wet_path_r, &mut self.output_stage_r),
+        ]
+        .iter_mut()
+        {
+            wet_path.first.first.set_bypassed(!saturate_pre);
+            output_stage.second.set_bypassed(!saturate_post);
+        }
+
+        //Keeps the dry signal lined up with the processed one
+        let latency = self.dynamics_latency();
+        debug_assert!(latency <= MAX_DRY_DELAY);
+        self.dry_delay_l.set_delay(latency);
+        self.dry_delay_r.set_delay(latency);
+    }
+
+    //Pushes the current parameter values into the single band & per band compressors
+    fn update_dynamics(&mut self) {
+        let params = &self.params;
+        let sample_rate = self.sample_rate.get() * self.oversampling as f32;
+        let stereo_link = StereoLink::from_param(params.stereo_link.get());
+        let transfer_curve = params.transfer_curve();
+        for compressor in std::iter::once(&mut self.stereo_compressor)
+            .chain(self.multiband.compressors.iter_mut())
+        {
+            compressor.mid_side = params.mid_side.get() >= 0.5;
+            compressor.stereo_link = stereo_link;
+            compressor.link = params.link.get() * 0.01;
+            compressor.side_threshold = params.side_threshold.get();
+            compressor.side_ratio = params.side_ratio.get();
+            compressor.set_feedback(params.topology.get() * 0.01);
+            compressor.set_smoothing(params.smoothing.get());
+            compressor.set_hold(params.hold.get());
+            compressor.set_mode(
+                Mode::from_param(params.mode.get()),
+                params.range.get(),
+                params.hysteresis.get(),
+                params.noise_floor.get(),
+            );
+            compressor.set_auto_release_shape(params.auto_release_shape.get() * 0.01);
+            compressor.set_knee_shape(KneeShape::from_param(params.knee_shape.get()));
+            compressor.set_transfer_curve(transfer_curve);
+        }
+
+        self.stereo_compressor.update_prams(
+            params.threshold.get(),
+            params.knee.get(),
+            params.pre_smooth.get(),
+            params.rms.get(),
+            params.ratio.get(),
+            params.attack.get(),
+            params.release.get(),
+            params.gain.get(),
+            sample_rate,
+        );
+
+        self.multiband.bands = (params.band_count.get().round() as usize).clamp(1, MAX_BANDS);
+        self.multiband.set_sample_rate(sample_rate);
+        self.multiband.set_crossovers([
+            params.crossover_1.get(),
+            params.crossover_2.get(),
+            params.crossover_3.get(),
+        ]);
+        for (band, band_params) in params.bands.iter().enumerate() {
+            self.multiband.compressors[band].update_prams(
+                band_params.threshold.get(),
+                params.knee.get(),
+                params.pre_smooth.get(),
+                params.rms.get(),
+                band_params.ratio.get(),
+                band_params.attack.get(),
+                band_params.release.get(),
+                band_params.gain.get(),
+                sample_rate,
+            );
+            let band_gain = &mut self.multiband.band_gain[band];
+            band_gain.set_ramp(params.smoothing.get(), sample_rate);
+            band_gain.set_target(db_to_lin(T::from_f32(band_params.gain.get())));
+            self.multiband.bypass[band] = band_params.bypass.get() >= 0.5;
+        }
+    }
+
+    //Single band or multiband compression of one (possibly oversampled) sample
+    fn process_dynamics(&mut self, audio: (T, T), key: (T, T)) -> ((T, T), (T, T)) {
+        if self.multiband.bands > 1 {
+            self.multiband.process(audio, key)
+        } else {
+            self.stereo_compressor.process(audio, key)
+        }
+    }
+
+    //Runs the compressor at `oversampling` times the host rate
+    //Returns the left & right output and the most attenuation within the sample
+    fn process_oversampled(&mut self, audio: (T, T), key: (T, T)) -> ((T, T), (T, T)) {
+        let factor = self.oversampling;
+        let mut audio_l = [T::default(); MAX_FACTOR];
+        let mut audio_r = [T::default(); MAX_FACTOR];
+        let mut key_l = [T::default(); MAX_FACTOR];
+        let mut key_r = [T::default(); MAX_FACTOR];
+        self.upsampler_l.process(audio.0, &mut audio_l);
+        self.upsampler_r.process(audio.1, &mut audio_r);
+        self.key_upsampler_l.process(key.0, &mut key_l);
+        self.key_upsampler_r.process(key.1, &mut key_r);
+
+        let mut cv = (T::from_f64(1.0), T::from_f64(1.0));
+        for i in 0..factor {
+            let (out, sub_cv) =
+                self.process_dynamics((audio_l[i], audio_r[i]), (key_l[i], key_r[i]));
+            audio_l[i] = out.0;
+            audio_r[i] = out.1;
+            cv = (cv.0.min(sub_cv.0), cv.1.min(sub_cv.1));
+        }
+
+        let out = (
+            self.downsampler_l.process(&audio_l[..factor]),
+            self.downsampler_r.process(&audio_r[..factor]),
+        );
+        (out, cv)
+    }
+
+    //Call update_latency first
+    fn process(
+        &mut self,
+        inputs: (&[T], &[T]),
+        sidechain: (&[T], &[T]),
+        outputs: (&mut [T], &mut [T]),
+        sample_producer: &mut Producer<editor::Sample>,
+    ) {
+        self.update_dynamics();
+
+        self.true_peak_meters = self.params.meters.get() >= 0.5;
+
+        self.limiter.set_ceiling(self.params.limiter_ceiling.get());
+
+        for key_path in [&mut self.key_path_l, &mut self.key_path_r].iter_mut() {
+            key_path.first.first.update(
+                self.params.sc_hp_freq.get(),
+                self.params.sc_lp_freq.get(),
+                self.params.sc_bell_freq.get(),
+                self.params.sc_bell_q.get(),
+                self.params.sc_bell_gain.get(),
+            );
+        }
+
+        //Makes up for downward compression only
+        let mode = Mode::from_param(self.params.mode.get());
+        let makeup = if mode != Mode::Compress && mode != Mode::Custom {
+            0.0
+        } else {
+            match self.params.auto_makeup.get().round() as i32 {
+                0 => 0.0,
+                1 if mode == Mode::Custom => self.params.transfer_curve().makeup(),
+                1 => compressor::curve_makeup(
+                    self.params.threshold.get(),
+                    self.params.knee.get(),
+                    self.params.ratio.get(),
+                    KneeShape::from_param(self.params.knee_shape.get()),
+                ),
+                _ => self.measured_makeup,
+            }
+        };
+        let smoothing = self.params.smoothing.get();
+        let gain = self.params.gain.get();
+        let (wet_gain, sum_gain) = if self.params.gain_on_sum.get() >= 0.5 {
+            (0.0, gain)
+        } else {
+            (gain, 0.0)
+        };
+        let drive = self.params.drive.get();
+        for (wet_path, output_stage) in [
+            (&mut self.wet_path_l, &mut self.output_stage_l),
+            (&mut self.wet_path_r, &mut self.output_stage_r),
+        ]
+        .iter_mut()
+        {
+            let pre_saturator = &mut wet_path.first.first.processor;
+            pre_saturator.set_smoothing(smoothing);
+            pre_saturator.update(self.saturation, drive);
+            let makeup_gain = &mut wet_path.first.second;
+            makeup_gain.set_smoothing(smoothing);
+            makeup_gain.set_gain(makeup);
+            wet_path.second.set_smoothing(smoothing);
+            wet_path.second.set_gain(wet_gain);
+            output_stage.first.set_smoothing(smoothing);
+            output_stage.first.set_gain(sum_gain);
+            let post_saturator = &mut output_stage.second.processor;
+            post_saturator.set_smoothing(smoothing);
+            post_saturator.update(self.saturation, drive);
+        }
+        self.mix_smoother
+            .set_ramp(smoothing, self.sample_rate.get());
+        self.mix_smoother
+            .set_target(T::from_f32(self.params.mix.get() * 0.01));
+        self.makeup = makeup;
+
+        let (inputs_left, inputs_right) = inputs;
+        let (keys_left, keys_right) = if self.params.sidechain.get() >= 0.5 {
+            sidechain
+        } else {
+            inputs
+        };
+        let (outputs_left, outputs_right) = outputs;
+
+        let samples = inputs_left.len();
+        let mut start = 0;
+        while start < samples {
+            let end = (start + BLOCK_SIZE).min(samples);
+            self.process_chunk(
+                (&inputs_left[start..end], &inputs_right[start..end]),
+                (&keys_left[start..end], &keys_right[start..end]),
+                (
+                    &mut outputs_left[start..end],
+                    &mut outputs_right[start..end],
+                ),
+                sample_producer,
+            );
+            start = end;
+        }
+    }
+
+    //Runs each stage over up to BLOCK_SIZE samples before moving on to the next
+    fn process_chunk(
+        &mut self,
+        inputs: (&[T], &[T]),
+        keys: (&[T], &[T]),
+        outputs: (&mut [T], &mut [T]),
+        sample_producer: &mut Producer<editor::Sample>,
+    ) {
+        let samples = inputs.0.len();
+        let (inputs_left, inputs_right) = inputs;
+        let (outputs_left, outputs_right) = outputs;
+
+        let mut key_l = [T::default(); BLOCK_SIZE];
+        let mut key_r = [T::default(); BLOCK_SIZE];
+        let mut audio_l = [T::default(); BLOCK_SIZE];
+        let mut audio_r = [T::default(); BLOCK_SIZE];
+        let mut cv_l = [T::default(); BLOCK_SIZE];
+        let mut cv_r = [T::default(); BLOCK_SIZE];
+        let (key_l, key_r) = (&mut key_l[..samples], &mut key_r[..samples]);
+        let (audio_l, audio_r) = (&mut audio_l[..samples], &mut audio_r[..samples]);
+        let (cv_l, cv_r) = (&mut cv_l[..samples], &mut cv_r[..samples]);
+
+        key_l.copy_from_slice(keys.0);
+        key_r.copy_from_slice(keys.1);
+        self.key_path_l.process_block(key_l);
+        self.key_path_r.process_block(key_r);
+
+        //Delay the audio so the detector sees transients before they arrive
+        audio_l.copy_from_slice(inputs_left);
+        audio_r.copy_from_slice(inputs_right);
+        self.lookahead_l.process_block(audio_l);
+        self.lookahead_r.process_block(audio_r);
+
+        let mut energy_in = [T::default(); BLOCK_SIZE];
+        let energy_in = &mut energy_in[..samples];
+        for (energy, (x_l, x_r)) in energy_in.iter_mut().zip(audio_l.iter().zip(audio_r.iter())) {
+            *energy = *x_l * *x_l + *x_r * *x_r;
+        }
+
+        if self.oversampling > 1 || self.multiband.bands > 1 {
+            for i in 0..samples {
+                let audio = (audio_l[i], audio_r[i]);
+                let key = (key_l[i], key_r[i]);
+                let (out, cv) = if self.oversampling > 1 {
+                    self.process_oversampled(audio, key)
+                } else {
+                    self.process_dynamics(audio, key)
+                };
+                audio_l[i] = out.0;
+                audio_r[i] = out.1;
+                cv_l[i] = cv.0;
+                cv_r[i] = cv.1;
+            }
+        } else {
+            self.stereo_compressor
+                .process_block((audio_l, audio_r), (key_l, key_r), (cv_l, cv_r));
+        }
+        //The measured makeup is the reduction of the summed output, straight from the levels
+        //going into & out of the dynamics rather than the smoothed meter values, so it
+        //doesn't lag and takes every band into account
+        for i in 0..samples {
+            self.makeup_energy.0 += energy_in[i];
+            self.makeup_energy.1 += audio_l[i] * audio_l[i] + audio_r[i] * audio_r[i];
+            self.makeup_i += 1;
+            if self.makeup_i >= (self.sample_rate.get() as u32) / MAKEUP_RATE {
+                let (energy_in, energy_out) = self.makeup_energy;
+                let reduction = 10.0 * (energy_in / energy_out).log10().to_f32();
+                if reduction.is_finite() {
+                    self.measured_makeup = self.makeup_average.process(reduction);
+                }
+                self.makeup_energy = (T::default(), T::default());
+                self.makeup_i = 0;
+            }
+        }
+
+        self.wet_path_l.process_block(audio_l);
+        self.wet_path_r.process_block(audio_r);
+
+        //The key is done with, its buffers hold the delayed dry signal from here
+        let (dry_l, dry_r) = (key_l, key_r);
+        dry_l.copy_from_slice(inputs_left);
+        dry_r.copy_from_slice(inputs_right);
+        self.dry_delay_l.process_block(dry_l);
+        self.dry_delay_r.process_block(dry_r);
+        for i in 0..samples {
+            let mix = self.mix_smoother.next();
+            outputs_left[i] = dry_l[i] + mix * (audio_l[i] - dry_l[i]);
+            outputs_right[i] = dry_r[i] + mix * (audio_r[i] - dry_r[i]);
+        }
+
+        self.output_stage_l.process_block(outputs_left);
+        self.output_stage_r.process_block(outputs_right);
+
+        if self.limiter_enabled {
+            for (output_l, output_r) in outputs_left.iter_mut().zip(outputs_right.iter_mut()) {
+                let (limited_l, limited_r) = self.limiter.process((*output_l, *output_r));
+                *output_l = limited_l;
+                *output_r = limited_r;
+                self.limiter_gain_min = self.limiter_gain_min.min(self.limiter.gain().to_f32());
+            }
+        }
+
+        if self.true_peak_meters {
+            for i in 0..samples {
+                let values = [
+                    inputs_left[i],
+                    inputs_right[i],
+                    outputs_left[i],
+                    outputs_right[i],
+                ];
+                for ((detector, max), x) in self
+                    .meter_true_peaks
+                    .iter_mut()
+                    .zip(self.meter_true_peak_max.iter_mut())
+                    .zip(values.iter())
+                {
+                    *max = max.max(detector.process(*x).abs().to_f32());
+                }
+            }
+        }
+
+        //Metering, the dry & wet buffers are reused for the input & output levels
+        self.cv_lpf_l.process_block(cv_l);
+        self.cv_lpf_r.process_block(cv_r);
+
+        let (amp_filtered_l, amp_filtered_r) = (dry_l, dry_r);
+        amp_filtered_l.copy_from_slice(inputs_left);
+        amp_filtered_r.copy_from_slice(inputs_right);
+        self.amplitude_lpf_l.process_block(amp_filtered_l);
+        self.amplitude_lpf_r.process_block(amp_filtered_r);
+
+        let mut amp_rms_l = [T::default(); BLOCK_SIZE];
+        let mut amp_rms_r = [T::default(); BLOCK_SIZE];
+        let (amp_rms_l, amp_rms_r) = (&mut amp_rms_l[..samples], &mut amp_rms_r[..samples]);
+        amp_rms_l.copy_from_slice(inputs_left);
+        amp_rms_r.copy_from_slice(inputs_right);
+        self.amplitude_rms_l.process_block(amp_rms_l);
+        self.amplitude_rms_r.process_block(amp_rms_r);
+
+        let (amp_rms_out_l, amp_rms_out_r) = (audio_l, audio_r);
+        amp_rms_out_l.copy_from_slice(outputs_left);
+        amp_rms_out_r.copy_from_slice(outputs_right);
+        self.amplitude_rms_out_l.process_block(amp_rms_out_l);
+        self.amplitude_rms_out_r.process_block(amp_rms_out_r);
+
+        for i in 0..samples {
+            let cv_filtered_l = cv_l[i].to_f32();
+            let cv_filtered_r = cv_r[i].to_f32();
+
+            if self.data_i >= (self.sample_rate.get() as u32) / 512 {
+                if !sample_producer.is_full() {
+                    let mut band_cv = [1.0; MAX_BANDS];
+                    for (meter, cv) in band_cv.iter_mut().zip(self.multiband.band_cv.iter()) {
+                        *meter = cv.to_f32();
+                    }
+                    sample_producer
+                        .push(editor::Sample {
+                            left: amp_filtered_l[i].to_f32(),
+                            right: amp_filtered_r[i].to_f32(),
+                            left_rms: amp_rms_l[i].to_f32(),
+                            right_rms: amp_rms_r[i].to_f32(),
+                            cv_l: cv_filtered_l,
+                            cv_r: cv_filtered_r,
+                            out_left_rms: amp_rms_out_l[i].to_f32(),
+                            out_right_rms: amp_rms_out_r[i].to_f32(),
+                            band_cv,
+                            makeup: self.makeup,
+                            true_peak: self.meter_true_peak_max,
+                            limiter_gain: self.limiter_gain_min,
+                        })
+                        .unwrap_or(());
+                }
+                self.meter_true_peak_max = [0.0; 4];
+                self.limiter_gain_min = 1.0;
+                self.data_i = 0;
+            }
+            self.data_i += 1;
+        }
+    }
 }
 
 fn setup_logging() {
@@ -101,35 +957,51 @@ fn setup_logging() {
 }
 
 impl Plugin for CompressorPlugin {
+    fn new(host: HostCallback) -> Self {
+        CompressorPlugin::with_host(host)
+    }
+
     fn get_info(&self) -> Info {
         Info {
             name: "IMGUI Compressor in Rust 0.1".to_string(),
             vendor: "DGriffin".to_string(),
             unique_id: 243123123,
             version: 2,
-            inputs: 2,
+            // Inputs 0 & 1 are the main stereo pair, 2 & 3 are the external sidechain
+            inputs: 4,
             outputs: 2,
+            // Hosts with a 64-bit engine call process_f64 instead of converting
+            f64_precision: true,
             // This `parameters` bit is important; without it, none of our
             // parameters will be shown!
             parameters: self.params.len() as i32,
+            // For MIDI Automation
+            midi_inputs: 1,
             category: Category::Effect,
+            initial_delay: self.latency.reported() as i32,
             ..Default::default()
         }
     }
 
+    fn get_input_info(&self, input: i32) -> ChannelInfo {
+        let name = match input {
+            0 => "Input L",
+            1 => "Input R",
+            2 => "Sidechain L",
+            _ => "Sidechain R",
+        };
+        ChannelInfo::new(name.to_string(), None, true, None)
+    }
+
     fn set_sample_rate(&mut self, rate: f32) {
         self.sample_rate.set(rate);
-        self.cv_lpf.set_sample_rate(rate);
-        self.amplitude_lpf_l.set_sample_rate(rate);
-        self.amplitude_lpf_r.set_sample_rate(rate);
-        self.amplitude_rms_l
-            .resize(rate as usize, self.params.rms.get());
-        self.amplitude_rms_r
-            .resize(rate as usize, self.params.rms.get());
+        self.dsp.set_sample_rate(rate);
+        self.dsp_f64.set_sample_rate(rate);
+        self.update_latency();
     }
 
-    fn set_block_size(&mut self, block_size: i64) {
-        self.block_size = block_size;
+    fn resume(&mut self) {
+        self.update_latency();
     }
 
     fn init(&mut self) {
@@ -145,63 +1017,68 @@ impl Plugin for CompressorPlugin {
     }
 
     fn process(&mut self, buffer: &mut AudioBuffer<f32>) {
-        self.compressor.update_prams(
-            self.params.threshold.get(),
-            self.params.knee.get(),
-            self.params.pre_smooth.get(),
-            self.params.rms.get(),
-            self.params.ratio.get(),
-            self.params.attack.get(),
-            self.params.release.get(),
-            self.params.gain.get(),
-            self.sample_rate.get(),
+        let (inputs, outputs) = buffer.split();
+        let (inputs_left, inputs_right) = (inputs.get(0), inputs.get(1));
+        //Fall back to the main input if the host didn't connect the sidechain pins
+        let (keys_left, keys_right) = if inputs.len() >= 4 {
+            (inputs.get(2), inputs.get(3))
+        } else {
+            (inputs_left, inputs_right)
+        };
+        let (mut outputs_left, mut outputs_right) = outputs.split_at_mut(1);
+        self.process_block(
+            (inputs_left, inputs_right),
+            (keys_left, keys_right),
+            (&mut outputs_left[0], &mut outputs_right[0]),
         );
+    }
 
-        self.time
-            .set(self.time.get() + (1.0 / self.sample_rate.get()) * self.block_size as f32);
-
-        let gain = db_to_lin(self.params.gain.get());
-
+    fn process_f64(&mut self, buffer: &mut AudioBuffer<f64>) {
         let (inputs, outputs) = buffer.split();
-        let (inputs_left, inputs_right) = inputs.split_at(1);
+        let (inputs_left, inputs_right) = (inputs.get(0), inputs.get(1));
+        //Fall back to the main input if the host didn't connect the sidechain pins
+        let (keys_left, keys_right) = if inputs.len() >= 4 {
+            (inputs.get(2), inputs.get(3))
+        } else {
+            (inputs_left, inputs_right)
+        };
         let (mut outputs_left, mut outputs_right) = outputs.split_at_mut(1);
+        self.process_block(
+            (inputs_left, inputs_right),
+            (keys_left, keys_right),
+            (&mut outputs_left[0], &mut outputs_right[0]),
+        );
+    }
 
-        let inputs_stereo = inputs_left[0].iter().zip(inputs_right[0].iter());
-        let outputs_stereo = outputs_left[0].iter_mut().zip(outputs_right[0].iter_mut());
-
-        for (input_pair, output_pair) in inputs_stereo.zip(outputs_stereo) {
-            let (input_l, input_r) = input_pair;
-            let (output_l, output_r) = output_pair;
-
-            let detector_input = (input_l + input_r).abs() * 0.5;
-
-            let cv = self.compressor.process(detector_input);
-
-            *output_l = *input_l * cv * gain;
-            *output_r = *input_r * cv * gain;
-
-            let cv_filtered = self.cv_lpf.process(cv);
-
-            let amp_filtered_l = self.amplitude_lpf_l.process(*input_l);
-            let amp_filtered_r = self.amplitude_lpf_r.process(*input_r);
-
-            let amp_rms_l = self.amplitude_rms_l.process(*input_l);
-            let amp_rms_r = self.amplitude_rms_r.process(*input_r);
-            if self.data_i >= (self.sample_rate.get() as u32) / 512 {
-                if !self.sample_producer.is_full() {
-                    self.sample_producer
-                        .push(editor::Sample {
-                            left: amp_filtered_l,
-                            right: amp_filtered_r,
-                            left_rms: amp_rms_l,
-                            right_rms: amp_rms_r,
-                            cv: cv_filtered,
-                        })
-                        .unwrap_or(());
+    //With MIDI Automation on, control change n sets parameter n at the event's sample, on any
+    //channel. Sequencers that can draw CCs get sample accurate automation this way, which VST2
+    //parameter automation doesn't have.
+    fn process_events(&mut self, events: &Events) {
+        if self.params.midi_automation.get() < 0.5 {
+            return;
+        }
+        for event in events.events() {
+            if let Event::Midi(midi) = event {
+                let [status, controller, value] = midi.data;
+                let index = controller as usize;
+                if status & 0xF0 == CONTROL_CHANGE
+                    && controller < CHANNEL_MODE
+                    && index < self.params.len()
+                {
+                    self.queue_event(ParameterEvent {
+                        offset: midi.delta_frames.max(0) as usize,
+                        index,
+                        value: value as f32 / 127.0,
+                    });
                 }
-                self.data_i = 0;
             }
-            self.data_i += 1;
+        }
+    }
+
+    fn can_do(&self, can_do: CanDo) -> Supported {
+        match can_do {
+            CanDo::ReceiveEvents | CanDo::ReceiveMidiEvent => Supported::Yes,
+            _ => Supported::Maybe,
         }
     }
 
@@ -223,6 +1100,10 @@ impl PluginParameters for CompressorEffectParameters {
     }
 
     // the `set_parameter` function sets the value of a parameter.
+    // VST2 gives no sample offset with a change, and hosts may call this from any thread at any
+    // point relative to process, so it applies right away and lands between blocks at best.
+    // Sample accurate changes need a source that knows the offset, see `schedule` and
+    // `CompressorPlugin::process_events`.
     fn set_parameter(&self, index: i32, val: f32) {
         #[allow(clippy::single_match)]
         if (index as usize) < self.len() {
@@ -252,3 +1133,47 @@ impl PluginParameters for CompressorEffectParameters {
 }
 
 plugin_main!(CompressorPlugin);
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SAMPLE_RATE: f32 = 48000.0;
+    const BLOCK: usize = 512;
+    //Quiet enough to stay under the threshold
+    const LEVEL: f32 = 0.01;
+
+    //Runs a block of steady input, returning the left output
+    fn process(plugin: &mut CompressorPlugin) -> Vec<f32> {
+        let input = vec![LEVEL; BLOCK];
+        let (mut left, mut right) = (vec![0.0; BLOCK], vec![0.0; BLOCK]);
+        plugin.process_block((&input, &input), (&input, &input), (&mut left, &mut right));
+        left
+    }
+
+    #[test]
+    fn scheduled_change_lands_on_its_sample() {
+        const OFFSET: usize = 100;
+        const GAIN_INDEX: usize = 7;
+        let mut plugin = CompressorPlugin::default();
+        plugin.set_sample_rate(SAMPLE_RATE);
+        plugin.params.smoothing.set(0.0);
+        //Settle the filters and delays on the steady input
+        process(&mut plugin);
+        process(&mut plugin);
+        //+12dB
+        assert!(plugin.params.schedule(OFFSET, GAIN_INDEX, 0.75));
+        let output = process(&mut plugin);
+        let gain = db_to_lin(12.0);
+        for (i, &x) in output.iter().enumerate() {
+            let expected = if i < OFFSET { LEVEL } else { LEVEL * gain };
+            assert!(
+                (x - expected).abs() < expected * 1e-3,
+                "sample {}: {} instead of {}",
+                i,
+                x,
+                expected
+            );
+        }
+    }
+}
@@ -0,0 +1,166 @@
+use crate::units::{db_to_lin, Float, VariableRingBuffer};
+
+//How far ahead the limiter looks, in ms
+const LOOKAHEAD: f32 = 1.5;
+//1.5ms at 384khz
+const MAX_LOOKAHEAD_SAMPLES: usize = 576;
+const RELEASE: f32 = 50.0;
+
+//Largest of the last `size` values pushed, in amortized constant time. Keeps a deque of the
+//values that are larger than everything pushed after them, so its front is the maximum.
+struct RunningMax<T: Float> {
+    //Ring storage of (push count, value)
+    values: Vec<(usize, T)>,
+    front: usize,
+    len: usize,
+    size: usize,
+    pushed: usize,
+}
+
+impl<T: Float> RunningMax<T> {
+    fn new(max_size: usize) -> RunningMax<T> {
+        RunningMax {
+            values: vec![(0, T::default()); max_size],
+            front: 0,
+            len: 0,
+            size: max_size,
+            pushed: 0,
+        }
+    }
+
+    //Clears the window
+    fn resize(&mut self, size: usize) {
+        self.size = size.clamp(1, self.values.len());
+        self.front = 0;
+        self.len = 0;
+        self.pushed = 0;
+    }
+
+    //Returns the maximum of the window including x
+    fn push(&mut self, x: T) -> T {
+        let capacity = self.values.len();
+        if self.len > 0 && self.pushed - self.values[self.front].0 >= self.size {
+            self.front = (self.front + 1) % capacity;
+            self.len -= 1;
+        }
+        while self.len > 0 && self.values[(self.front + self.len - 1) % capacity].1 <= x {
+            self.len -= 1;
+        }
+        self.values[(self.front + self.len) % capacity] = (self.pushed, x);
+        self.len += 1;
+        self.pushed += 1;
+        self.values[self.front].1
+    }
+}
+
+//Stereo linked lookahead brickwall limiter. The gain needed to keep each sample under the
+//ceiling is held at its minimum over the lookahead, released, then averaged over the
+//lookahead so it has fully ramped down by the time the peak leaves the delay line.
+pub struct Limiter<T: Float> {
+    ceiling: T,
+    release: T,
+    lookahead: usize,
+    sample_rate: f32,
+
+    delay_l: VariableRingBuffer<T>,
+    delay_r: VariableRingBuffer<T>,
+    gains: RunningMax<T>,
+    env: T,
+    averages: VariableRingBuffer<T>,
+    sum: T,
+    gain: T,
+}
+
+impl<T: Float> Limiter<T> {
+    pub fn new(sample_rate: f32) -> Limiter<T> {
+        let mut limiter = Limiter {
+            ceiling: T::from_f64(1.0),
+            release: T::default(),
+            lookahead: 0,
+            sample_rate: 0.0,
+
+            delay_l: VariableRingBuffer::new(1, MAX_LOOKAHEAD_SAMPLES),
+            delay_r: VariableRingBuffer::new(1, MAX_LOOKAHEAD_SAMPLES),
+            gains: RunningMax::new(MAX_LOOKAHEAD_SAMPLES + 1),
+            env: T::from_f64(1.0),
+            averages: VariableRingBuffer::new(1, MAX_LOOKAHEAD_SAMPLES),
+            sum: T::default(),
+            gain: T::from_f64(1.0),
+        };
+        limiter.set_sample_rate(sample_rate);
+        limiter
+    }
+
+    //ceiling units are dB
+    pub fn set_ceiling(&mut self, ceiling: f32) {
+        self.ceiling = db_to_lin(T::from_f32(ceiling));
+    }
+
+    pub fn set_sample_rate(&mut self, sample_rate: f32) {
+        if sample_rate != self.sample_rate {
+            self.sample_rate = sample_rate;
+            self.release = (-T::PI * T::from_f32(1000.0 / RELEASE / sample_rate)).exp();
+            self.lookahead = ((LOOKAHEAD * 0.001 * sample_rate).round() as usize)
+                .clamp(1, MAX_LOOKAHEAD_SAMPLES);
+            self.reset();
+        }
+    }
+
+    pub fn reset(&mut self) {
+        self.delay_l.resize(self.lookahead);
+        self.delay_r.resize(self.lookahead);
+        self.gains.resize(self.lookahead + 1);
+        self.averages.resize(self.lookahead);
+        //The buffers are zeroed, which is a gain of 1.0 stored as 1.0 - gain. The reductions
+        //are never negative, so an empty window is no reduction too.
+        self.env = T::from_f64(1.0);
+        self.sum = T::default();
+        self.gain = T::from_f64(1.0);
+    }
+
+    //Delay added to the audio, in samples
+    pub fn latency(&self) -> usize {
+        self.lookahead
+    }
+
+    //The gain applied to the last sample
+    pub fn gain(&self) -> T {
+        self.gain
+    }
+
+    pub fn process(&mut self, audio: (T, T)) -> (T, T) {
+        let one = T::from_f64(1.0);
+        let peak = audio.0.abs().max(audio.1.abs());
+        let target = if peak > self.ceiling {
+            self.ceiling / peak
+        } else {
+            one
+        };
+
+        //Gains are stored as reduction (1.0 - gain) so the zeroed buffers mean no reduction
+        let reduction = self.gains.push(one - target);
+
+        //Instant attack keeps the envelope at or below every gain in the window
+        let held = one - reduction;
+        self.env = if held < self.env {
+            held
+        } else {
+            held + self.release * (self.env - held)
+        };
+
+        self.sum += -self.averages.oldest() + (one - self.env);
+        self.averages.push(one - self.env);
+        self.gain = one - self.sum / T::from_f64(self.averages.size() as f64);
+
+        let delayed_l = self.delay_l.oldest();
+        let delayed_r = self.delay_r.oldest();
+        self.delay_l.push(audio.0);
+        self.delay_r.push(audio.1);
+
+        //Guards against rounding in the running sum
+        (
+            (delayed_l * self.gain).max(-self.ceiling).min(self.ceiling),
+            (delayed_r * self.gain).max(-self.ceiling).min(self.ceiling),
+        )
+    }
+}
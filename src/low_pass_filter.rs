@@ -1,26 +1,30 @@
-use std::f32::consts::PI;
-
-pub struct LowPassFilter {
-    fd0: f32,
-    fd1: f32,
-    fd2: f32,
-    fa0: f32,
-    fa1: f32,
-    fk: f32,
+use crate::processor::Processor;
+use crate::units::Float;
+
+//sharp value that gives the LowPassFilter/HighPassFilter topology a Butterworth response
+pub const BUTTERWORTH_SHARP: f32 = (std::f32::consts::SQRT_2 - 0.01) / 20.0;
+
+pub struct LowPassFilter<T: Float> {
+    fd0: T,
+    fd1: T,
+    fd2: T,
+    fa0: T,
+    fa1: T,
+    fk: T,
     freq: f32,
     sharp: f32,
     sample_rate: f32,
 }
 
-impl LowPassFilter {
-    pub fn new(freq: f32, sharp: f32, sample_rate: f32) -> LowPassFilter {
+impl<T: Float> LowPassFilter<T> {
+    pub fn new(freq: f32, sharp: f32, sample_rate: f32) -> LowPassFilter<T> {
         LowPassFilter {
-            fd0: 0.0,
-            fd1: 0.0,
-            fd2: 0.0,
-            fa0: 0.0,
-            fa1: 0.0,
-            fk: 0.0,
+            fd0: T::default(),
+            fd1: T::default(),
+            fd2: T::default(),
+            fa0: T::default(),
+            fa1: T::default(),
+            fk: T::default(),
             freq,
             sharp,
             sample_rate,
@@ -43,18 +47,310 @@ impl LowPassFilter {
     }
 
     fn update(&mut self) {
-        let damp = 0.01 + self.sharp * 20.0;
-        let c = 1.0 / (PI * self.freq / self.sample_rate).tan();
-        self.fk = 1.0 / (1.0 + c * (c + damp));
-        self.fa1 = 2.0 * (1.0 - c * c) * self.fk;
-        self.fa0 = (1.0 + c * (c - damp)) * self.fk;
+        let one = T::from_f64(1.0);
+        let damp = T::from_f32(0.01 + self.sharp * 20.0);
+        let c = one / (T::PI * T::from_f32(self.freq) / T::from_f32(self.sample_rate)).tan();
+        self.fk = one / (one + c * (c + damp));
+        self.fa1 = T::from_f64(2.0) * (one - c * c) * self.fk;
+        self.fa0 = (one + c * (c - damp)) * self.fk;
     }
 
-    pub fn process(&mut self, x: f32) -> f32 {
+    pub fn process(&mut self, x: T) -> T {
         self.fd0 = (self.fk * x) - (self.fa1 * self.fd1) - (self.fa0 * self.fd2);
         let y = self.fd0 + self.fd1 + self.fd1 + self.fd2;
         self.fd2 = self.fd1;
         self.fd1 = self.fd0;
         y
     }
+
+    pub fn reset(&mut self) {
+        self.fd0 = T::default();
+        self.fd1 = T::default();
+        self.fd2 = T::default();
+    }
+
+    //Filters the buffer in place, with the state kept in locals
+    pub fn process_block(&mut self, buffer: &mut [T]) {
+        let (fk, fa0, fa1) = (self.fk, self.fa0, self.fa1);
+        let (mut fd1, mut fd2) = (self.fd1, self.fd2);
+        for x in buffer.iter_mut() {
+            let fd0 = (fk * *x) - (fa1 * fd1) - (fa0 * fd2);
+            *x = fd0 + fd1 + fd1 + fd2;
+            fd2 = fd1;
+            fd1 = fd0;
+        }
+        self.fd0 = fd1;
+        self.fd1 = fd1;
+        self.fd2 = fd2;
+    }
+}
+
+impl<T: Float> Processor<T> for LowPassFilter<T> {
+    fn reset(&mut self) {
+        LowPassFilter::reset(self);
+    }
+
+    fn set_sample_rate(&mut self, sample_rate: f32) {
+        LowPassFilter::set_sample_rate(self, sample_rate);
+    }
+
+    fn process(&mut self, x: T) -> T {
+        LowPassFilter::process(self, x)
+    }
+
+    fn process_block(&mut self, buffer: &mut [T]) {
+        LowPassFilter::process_block(self, buffer);
+    }
+}
+
+//Same topology as LowPassFilter, with the numerator swapped for (1 - z^-1)^2
+pub struct HighPassFilter<T: Float> {
+    fd0: T,
+    fd1: T,
+    fd2: T,
+    fa0: T,
+    fa1: T,
+    fk: T,
+    fc2: T,
+    freq: f32,
+    sharp: f32,
+    sample_rate: f32,
+}
+
+impl<T: Float> HighPassFilter<T> {
+    pub fn new(freq: f32, sharp: f32, sample_rate: f32) -> HighPassFilter<T> {
+        let mut filter = HighPassFilter {
+            fd0: T::default(),
+            fd1: T::default(),
+            fd2: T::default(),
+            fa0: T::default(),
+            fa1: T::default(),
+            fk: T::default(),
+            fc2: T::default(),
+            freq,
+            sharp,
+            sample_rate,
+        };
+        filter.update();
+        filter
+    }
+
+    pub fn set_freq(&mut self, freq: f32) {
+        self.freq = freq;
+        self.update();
+    }
+
+    pub fn set_sample_rate(&mut self, sample_rate: f32) {
+        self.sample_rate = sample_rate;
+        self.update();
+    }
+
+    fn update(&mut self) {
+        let one = T::from_f64(1.0);
+        let damp = T::from_f32(0.01 + self.sharp * 20.0);
+        let c = one / (T::PI * T::from_f32(self.freq) / T::from_f32(self.sample_rate)).tan();
+        self.fk = one / (one + c * (c + damp));
+        self.fa1 = T::from_f64(2.0) * (one - c * c) * self.fk;
+        self.fa0 = (one + c * (c - damp)) * self.fk;
+        self.fc2 = c * c;
+    }
+
+    pub fn process(&mut self, x: T) -> T {
+        self.fd0 = (self.fk * x) - (self.fa1 * self.fd1) - (self.fa0 * self.fd2);
+        let y = self.fc2 * (self.fd0 - self.fd1 - self.fd1 + self.fd2);
+        self.fd2 = self.fd1;
+        self.fd1 = self.fd0;
+        y
+    }
+
+    pub fn reset(&mut self) {
+        self.fd0 = T::default();
+        self.fd1 = T::default();
+        self.fd2 = T::default();
+    }
+
+    //Filters the buffer in place, with the state kept in locals
+    pub fn process_block(&mut self, buffer: &mut [T]) {
+        let (fk, fa0, fa1, fc2) = (self.fk, self.fa0, self.fa1, self.fc2);
+        let (mut fd1, mut fd2) = (self.fd1, self.fd2);
+        for x in buffer.iter_mut() {
+            let fd0 = (fk * *x) - (fa1 * fd1) - (fa0 * fd2);
+            *x = fc2 * (fd0 - fd1 - fd1 + fd2);
+            fd2 = fd1;
+            fd1 = fd0;
+        }
+        self.fd0 = fd1;
+        self.fd1 = fd1;
+        self.fd2 = fd2;
+    }
+}
+
+impl<T: Float> Processor<T> for HighPassFilter<T> {
+    fn reset(&mut self) {
+        HighPassFilter::reset(self);
+    }
+
+    fn set_sample_rate(&mut self, sample_rate: f32) {
+        HighPassFilter::set_sample_rate(self, sample_rate);
+    }
+
+    fn process(&mut self, x: T) -> T {
+        HighPassFilter::process(self, x)
+    }
+
+    fn process_block(&mut self, buffer: &mut [T]) {
+        HighPassFilter::process_block(self, buffer);
+    }
+}
+
+//Direct form II biquad with arbitrary coefficients, the filters that don't need their own
+//topology are designed into this
+pub struct Biquad<T: Float> {
+    fd0: T,
+    fd1: T,
+    fd2: T,
+    b0: T,
+    b1: T,
+    b2: T,
+    a1: T,
+    a2: T,
+}
+
+impl<T: Float> Default for Biquad<T> {
+    fn default() -> Biquad<T> {
+        Biquad::new()
+    }
+}
+
+impl<T: Float> Biquad<T> {
+    //Starts out passing the signal through unchanged
+    pub fn new() -> Biquad<T> {
+        Biquad {
+            fd0: T::default(),
+            fd1: T::default(),
+            fd2: T::default(),
+            b0: T::from_f64(1.0),
+            b1: T::default(),
+            b2: T::default(),
+            a1: T::default(),
+            a2: T::default(),
+        }
+    }
+
+    //b are the numerator & a the denominator coefficients, both are divided by a[0]
+    pub fn set_coefficients(&mut self, b: [T; 3], a: [T; 3]) {
+        self.b0 = b[0] / a[0];
+        self.b1 = b[1] / a[0];
+        self.b2 = b[2] / a[0];
+        self.a1 = a[1] / a[0];
+        self.a2 = a[2] / a[0];
+    }
+
+    pub fn process(&mut self, x: T) -> T {
+        self.fd0 = x - (self.a1 * self.fd1) - (self.a2 * self.fd2);
+        let y = self.b0 * self.fd0 + self.b1 * self.fd1 + self.b2 * self.fd2;
+        self.fd2 = self.fd1;
+        self.fd1 = self.fd0;
+        y
+    }
+
+    pub fn reset(&mut self) {
+        self.fd0 = T::default();
+        self.fd1 = T::default();
+        self.fd2 = T::default();
+    }
+
+    //Filters the buffer in place, with the state kept in locals
+    pub fn process_block(&mut self, buffer: &mut [T]) {
+        let (b0, b1, b2, a1, a2) = (self.b0, self.b1, self.b2, self.a1, self.a2);
+        let (mut fd1, mut fd2) = (self.fd1, self.fd2);
+        for x in buffer.iter_mut() {
+            let fd0 = *x - (a1 * fd1) - (a2 * fd2);
+            *x = b0 * fd0 + b1 * fd1 + b2 * fd2;
+            fd2 = fd1;
+            fd1 = fd0;
+        }
+        self.fd0 = fd1;
+        self.fd1 = fd1;
+        self.fd2 = fd2;
+    }
+}
+
+//Peaking EQ from the RBJ audio EQ cookbook
+pub struct BellFilter<T: Float> {
+    biquad: Biquad<T>,
+    freq: f32,
+    q: f32,
+    gain: f32,
+    sample_rate: f32,
+}
+
+impl<T: Float> BellFilter<T> {
+    pub fn new(freq: f32, q: f32, gain: f32, sample_rate: f32) -> BellFilter<T> {
+        let mut filter = BellFilter {
+            biquad: Biquad::new(),
+            freq,
+            q,
+            gain,
+            sample_rate,
+        };
+        filter.update();
+        filter
+    }
+
+    pub fn set(&mut self, freq: f32, q: f32, gain: f32) {
+        self.freq = freq;
+        self.q = q;
+        self.gain = gain;
+        self.update();
+    }
+
+    pub fn set_sample_rate(&mut self, sample_rate: f32) {
+        self.sample_rate = sample_rate;
+        self.update();
+    }
+
+    fn update(&mut self) {
+        let one = T::from_f64(1.0);
+        let two = T::from_f64(2.0);
+        let a = T::from_f64(10.0).powf(T::from_f32(self.gain / 40.0));
+        let w0 = two * T::PI * T::from_f32(self.freq) / T::from_f32(self.sample_rate);
+        let alpha = w0.sin() / (two * T::from_f32(self.q));
+        //b1 & a1 are the same for a peaking EQ
+        let b1 = -two * w0.cos();
+        self.biquad.set_coefficients(
+            [one + alpha * a, b1, one - alpha * a],
+            [one + alpha / a, b1, one - alpha / a],
+        );
+    }
+
+    pub fn process(&mut self, x: T) -> T {
+        self.biquad.process(x)
+    }
+
+    pub fn reset(&mut self) {
+        self.biquad.reset();
+    }
+
+    pub fn process_block(&mut self, buffer: &mut [T]) {
+        self.biquad.process_block(buffer);
+    }
+}
+
+impl<T: Float> Processor<T> for BellFilter<T> {
+    fn reset(&mut self) {
+        BellFilter::reset(self);
+    }
+
+    fn set_sample_rate(&mut self, sample_rate: f32) {
+        BellFilter::set_sample_rate(self, sample_rate);
+    }
+
+    fn process(&mut self, x: T) -> T {
+        BellFilter::process(self, x)
+    }
+
+    fn process_block(&mut self, buffer: &mut [T]) {
+        BellFilter::process_block(self, buffer);
+    }
 }
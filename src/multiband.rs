@@ -0,0 +1,236 @@
+use crate::low_pass_filter::{HighPassFilter, LowPassFilter, BUTTERWORTH_SHARP};
+use crate::parameter::Smoother;
+use crate::stereo::StereoCompressor;
+use crate::units::Float;
+
+pub const MAX_BANDS: usize = 4;
+
+//4th order Linkwitz-Riley split, two cascaded Butterworth sections per side.
+//The low & high outputs sum back to an allpass with a flat magnitude.
+struct LinkwitzRiley<T: Float> {
+    low_pass: [LowPassFilter<T>; 2],
+    high_pass: [HighPassFilter<T>; 2],
+    freq: f32,
+}
+
+impl<T: Float> LinkwitzRiley<T> {
+    fn new(freq: f32, sample_rate: f32) -> LinkwitzRiley<T> {
+        let mut crossover = LinkwitzRiley {
+            low_pass: [
+                LowPassFilter::new(freq, BUTTERWORTH_SHARP, sample_rate),
+                LowPassFilter::new(freq, BUTTERWORTH_SHARP, sample_rate),
+            ],
+            high_pass: [
+                HighPassFilter::new(freq, BUTTERWORTH_SHARP, sample_rate),
+                HighPassFilter::new(freq, BUTTERWORTH_SHARP, sample_rate),
+            ],
+            freq,
+        };
+        crossover.set_sample_rate(sample_rate);
+        crossover
+    }
+
+    fn set_freq(&mut self, freq: f32) {
+        if freq != self.freq {
+            self.freq = freq;
+            for filter in self.low_pass.iter_mut() {
+                filter.set_freq(freq);
+            }
+            for filter in self.high_pass.iter_mut() {
+                filter.set_freq(freq);
+            }
+        }
+    }
+
+    fn set_sample_rate(&mut self, sample_rate: f32) {
+        for filter in self.low_pass.iter_mut() {
+            filter.set_sample_rate(sample_rate);
+        }
+        for filter in self.high_pass.iter_mut() {
+            filter.set_sample_rate(sample_rate);
+        }
+    }
+
+    fn split(&mut self, x: T) -> (T, T) {
+        let low = self.low_pass[0].process(x);
+        let high = self.high_pass[0].process(x);
+        (
+            self.low_pass[1].process(low),
+            self.high_pass[1].process(high),
+        )
+    }
+
+    //Same phase shift as split() but without splitting, used to keep the lower bands
+    //in phase with bands that went through later crossovers
+    fn allpass(&mut self, x: T) -> T {
+        let (low, high) = self.split(x);
+        low + high
+    }
+}
+
+//Splits one channel into 2-4 bands that sum back with a flat magnitude
+struct BandSplitter<T: Float> {
+    crossovers: [LinkwitzRiley<T>; MAX_BANDS - 1],
+    //Phase compensation for band 1 against crossovers 2 & 3, and band 2 against crossover 3
+    band_1_allpass_2: LinkwitzRiley<T>,
+    band_1_allpass_3: LinkwitzRiley<T>,
+    band_2_allpass_3: LinkwitzRiley<T>,
+}
+
+impl<T: Float> BandSplitter<T> {
+    fn new(freqs: [f32; MAX_BANDS - 1], sample_rate: f32) -> BandSplitter<T> {
+        BandSplitter {
+            crossovers: [
+                LinkwitzRiley::new(freqs[0], sample_rate),
+                LinkwitzRiley::new(freqs[1], sample_rate),
+                LinkwitzRiley::new(freqs[2], sample_rate),
+            ],
+            band_1_allpass_2: LinkwitzRiley::new(freqs[1], sample_rate),
+            band_1_allpass_3: LinkwitzRiley::new(freqs[2], sample_rate),
+            band_2_allpass_3: LinkwitzRiley::new(freqs[2], sample_rate),
+        }
+    }
+
+    fn set_freqs(&mut self, freqs: [f32; MAX_BANDS - 1]) {
+        for (crossover, freq) in self.crossovers.iter_mut().zip(freqs.iter()) {
+            crossover.set_freq(*freq);
+        }
+        self.band_1_allpass_2.set_freq(freqs[1]);
+        self.band_1_allpass_3.set_freq(freqs[2]);
+        self.band_2_allpass_3.set_freq(freqs[2]);
+    }
+
+    fn set_sample_rate(&mut self, sample_rate: f32) {
+        for crossover in self.crossovers.iter_mut() {
+            crossover.set_sample_rate(sample_rate);
+        }
+        self.band_1_allpass_2.set_sample_rate(sample_rate);
+        self.band_1_allpass_3.set_sample_rate(sample_rate);
+        self.band_2_allpass_3.set_sample_rate(sample_rate);
+    }
+
+    //Bands above `bands` are left at 0.0
+    fn process(&mut self, x: T, bands: usize) -> [T; MAX_BANDS] {
+        let zero = T::default();
+        let (band_1, rest) = self.crossovers[0].split(x);
+        if bands <= 2 {
+            return [band_1, rest, zero, zero];
+        }
+        let (band_2, rest) = self.crossovers[1].split(rest);
+        let band_1 = self.band_1_allpass_2.allpass(band_1);
+        if bands == 3 {
+            return [band_1, band_2, rest, zero];
+        }
+        let (band_3, band_4) = self.crossovers[2].split(rest);
+        let band_1 = self.band_1_allpass_3.allpass(band_1);
+        let band_2 = self.band_2_allpass_3.allpass(band_2);
+        [band_1, band_2, band_3, band_4]
+    }
+}
+
+//Splits the audio & key into bands, each with its own stereo compressor and makeup gain.
+//The key is split with the same crossovers so each band's detector only hears its band.
+pub struct MultibandCompressor<T: Float> {
+    pub bands: usize,
+    pub compressors: Vec<StereoCompressor<T>>,
+    //Ramps like the output gain, so automating a band's gain doesn't zipper
+    pub band_gain: [Smoother<T>; MAX_BANDS],
+    pub bypass: [bool; MAX_BANDS],
+    //Attenuation of the most compressed channel in each band, for metering
+    pub band_cv: [T; MAX_BANDS],
+    audio_l: BandSplitter<T>,
+    audio_r: BandSplitter<T>,
+    key_l: BandSplitter<T>,
+    key_r: BandSplitter<T>,
+    sample_rate: f32,
+}
+
+impl<T: Float> MultibandCompressor<T> {
+    pub fn new(freqs: [f32; MAX_BANDS - 1], sample_rate: f32) -> MultibandCompressor<T> {
+        MultibandCompressor {
+            bands: 1,
+            compressors: (0..MAX_BANDS).map(|_| StereoCompressor::new()).collect(),
+            band_gain: [Smoother::new(T::from_f64(1.0)); MAX_BANDS],
+            bypass: [false; MAX_BANDS],
+            band_cv: [T::from_f64(1.0); MAX_BANDS],
+            audio_l: BandSplitter::new(freqs, sample_rate),
+            audio_r: BandSplitter::new(freqs, sample_rate),
+            key_l: BandSplitter::new(freqs, sample_rate),
+            key_r: BandSplitter::new(freqs, sample_rate),
+            sample_rate,
+        }
+    }
+
+    //Frequencies are kept in ascending order and below nyquist
+    pub fn set_crossovers(&mut self, freqs: [f32; MAX_BANDS - 1]) {
+        let max_freq = self.sample_rate * 0.49;
+        let mut sorted = [0.0; MAX_BANDS - 1];
+        let mut last = 0.0f32;
+        for (sorted, freq) in sorted.iter_mut().zip(freqs.iter()) {
+            last = last.max(*freq).min(max_freq);
+            *sorted = last;
+        }
+        for splitter in [
+            &mut self.audio_l,
+            &mut self.audio_r,
+            &mut self.key_l,
+            &mut self.key_r,
+        ]
+        .iter_mut()
+        {
+            splitter.set_freqs(sorted);
+        }
+    }
+
+    pub fn set_sample_rate(&mut self, sample_rate: f32) {
+        if sample_rate == self.sample_rate {
+            return;
+        }
+        self.sample_rate = sample_rate;
+        for splitter in [
+            &mut self.audio_l,
+            &mut self.audio_r,
+            &mut self.key_l,
+            &mut self.key_r,
+        ]
+        .iter_mut()
+        {
+            splitter.set_sample_rate(sample_rate);
+        }
+    }
+
+    //Returns the summed left & right output and the most attenuation across the bands
+    pub fn process(&mut self, audio: (T, T), key: (T, T)) -> ((T, T), (T, T)) {
+        let one = T::from_f64(1.0);
+        let audio_l = self.audio_l.process(audio.0, self.bands);
+        let audio_r = self.audio_r.process(audio.1, self.bands);
+        let key_l = self.key_l.process(key.0, self.bands);
+        let key_r = self.key_r.process(key.1, self.bands);
+
+        let mut band_gain = [one; MAX_BANDS];
+        for (gain, smoother) in band_gain.iter_mut().zip(self.band_gain.iter_mut()) {
+            *gain = smoother.next();
+        }
+
+        let mut out = (T::default(), T::default());
+        let mut cv = (one, one);
+        for band in 0..self.bands {
+            if self.bypass[band] {
+                out.0 += audio_l[band];
+                out.1 += audio_r[band];
+                self.band_cv[band] = one;
+                continue;
+            }
+            let (band_out, band_cv) = self.compressors[band]
+                .process((audio_l[band], audio_r[band]), (key_l[band], key_r[band]));
+            out.0 += band_out.0 * band_gain[band];
+            out.1 += band_out.1 * band_gain[band];
+            self.band_cv[band] = band_cv.0.min(band_cv.1);
+            cv = (cv.0.min(band_cv.0), cv.1.min(band_cv.1));
+        }
+        for band_cv in self.band_cv.iter_mut().skip(self.bands) {
+            *band_cv = one;
+        }
+        (out, cv)
+    }
+}
@@ -0,0 +1,150 @@
+use crate::units::Float;
+
+pub const MAX_FACTOR: usize = 8;
+
+//Length of the anti-imaging/anti-aliasing filters, in samples at the base rate.
+//Each filter is factor * FILTER_LENGTH + 1 taps long and linear phase, so an
+//up & down round trip delays the signal by exactly FILTER_LENGTH base rate samples.
+pub const FILTER_LENGTH: usize = 32;
+
+pub fn factor_from_param(x: f32) -> usize {
+    1 << (x.round().max(0.0) as usize).min(3)
+}
+
+pub fn factor_name(x: f32) -> String {
+    match factor_from_param(x) {
+        1 => "Off".to_string(),
+        factor => format!("{}x", factor),
+    }
+}
+
+//Blackman windowed sinc with the cutoff at the base rate nyquist, unity gain at DC
+fn design_filter<T: Float>(factor: usize, coefficients: &mut Vec<T>) {
+    use std::f64::consts::PI;
+    let length = factor * FILTER_LENGTH + 1;
+    let center = (length - 1) as f64 / 2.0;
+    let cutoff = 0.5 / factor as f64;
+    let mut taps = Vec::with_capacity(length);
+    for i in 0..length {
+        let x = i as f64 - center;
+        let sinc = if x == 0.0 {
+            2.0 * cutoff
+        } else {
+            (2.0 * PI * cutoff * x).sin() / (PI * x)
+        };
+        let phase = 2.0 * PI * i as f64 / (length - 1) as f64;
+        let window = 0.42 - 0.5 * phase.cos() + 0.08 * (2.0 * phase).cos();
+        taps.push(sinc * window);
+    }
+    let sum: f64 = taps.iter().sum();
+    coefficients.clear();
+    coefficients.extend(taps.iter().map(|c| T::from_f64(c / sum)));
+}
+
+//Polyphase interpolator, each input sample produces `factor` output samples
+pub struct Upsampler<T: Float> {
+    factor: usize,
+    coefficients: Vec<T>,
+    history: Vec<T>,
+    position: usize,
+}
+
+impl<T: Float> Upsampler<T> {
+    pub fn new(factor: usize) -> Upsampler<T> {
+        let mut upsampler = Upsampler {
+            factor: 0,
+            coefficients: Vec::with_capacity(MAX_FACTOR * FILTER_LENGTH + 1),
+            history: vec![T::default(); FILTER_LENGTH + 1],
+            position: 0,
+        };
+        upsampler.set_factor(factor);
+        upsampler
+    }
+
+    pub fn set_factor(&mut self, factor: usize) {
+        if factor != self.factor {
+            self.factor = factor;
+            design_filter(factor, &mut self.coefficients);
+            self.reset();
+        }
+    }
+
+    pub fn reset(&mut self) {
+        for x in self.history.iter_mut() {
+            *x = T::default();
+        }
+    }
+
+    //output must be at least `factor` samples long
+    pub fn process(&mut self, x: T, output: &mut [T]) {
+        let size = self.history.len();
+        self.position = (self.position + 1) % size;
+        self.history[self.position] = x;
+        for (phase, y) in output.iter_mut().take(self.factor).enumerate() {
+            let mut acc = T::default();
+            //Only every factor-th tap lines up with a non-zero (un-stuffed) input sample
+            for (j, c) in self.coefficients[phase..]
+                .iter()
+                .step_by(self.factor)
+                .enumerate()
+            {
+                acc += *c * self.history[(self.position + size - j) % size];
+            }
+            //Make up for the energy lost to zero stuffing
+            *y = acc * T::from_f64(self.factor as f64);
+        }
+    }
+}
+
+//Filters then keeps every `factor`-th sample
+pub struct Downsampler<T: Float> {
+    factor: usize,
+    coefficients: Vec<T>,
+    history: Vec<T>,
+    position: usize,
+}
+
+impl<T: Float> Downsampler<T> {
+    pub fn new(factor: usize) -> Downsampler<T> {
+        let mut downsampler = Downsampler {
+            factor: 0,
+            coefficients: Vec::with_capacity(MAX_FACTOR * FILTER_LENGTH + 1),
+            history: vec![T::default(); MAX_FACTOR * FILTER_LENGTH + 1],
+            position: 0,
+        };
+        downsampler.set_factor(factor);
+        downsampler
+    }
+
+    pub fn set_factor(&mut self, factor: usize) {
+        if factor != self.factor {
+            self.factor = factor;
+            design_filter(factor, &mut self.coefficients);
+            self.reset();
+        }
+    }
+
+    pub fn reset(&mut self) {
+        for x in self.history.iter_mut() {
+            *x = T::default();
+        }
+    }
+
+    //input must be at least `factor` samples long
+    pub fn process(&mut self, input: &[T]) -> T {
+        let size = self.history.len();
+        let mut acc = T::default();
+        for (i, x) in input.iter().take(self.factor).enumerate() {
+            self.position = (self.position + 1) % size;
+            self.history[self.position] = *x;
+            //Only the output samples that are kept get computed. Keeping the first one
+            //lines it up with the upsampler's first phase for a whole sample delay.
+            if i == 0 {
+                for (k, c) in self.coefficients.iter().enumerate() {
+                    acc += *c * self.history[(self.position + size - k) % size];
+                }
+            }
+        }
+        acc
+    }
+}
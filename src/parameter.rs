@@ -1,4 +1,4 @@
-use crate::units::{from_range, to_range};
+use crate::units::{from_range, to_range, Float};
 use vst::util::AtomicFloat;
 
 pub struct Parameter {
@@ -56,3 +56,62 @@ impl Parameter {
         self.name.clone()
     }
 }
+
+//Ramps linearly to a new target over a fixed time instead of jumping, call next once per sample
+#[derive(Clone, Copy)]
+pub struct Smoother<T: Float> {
+    value: T,
+    target: T,
+    step: T,
+    steps_left: usize,
+    ramp_samples: usize,
+}
+
+impl<T: Float> Smoother<T> {
+    pub fn new(value: T) -> Smoother<T> {
+        Smoother {
+            value,
+            target: value,
+            step: T::default(),
+            steps_left: 0,
+            ramp_samples: 0,
+        }
+    }
+
+    //ramp units are ms, 0.0 jumps straight to each new target
+    pub fn set_ramp(&mut self, ramp: f32, sample_rate: f32) {
+        self.ramp_samples = (ramp.max(0.0) * 0.001 * sample_rate).round() as usize;
+    }
+
+    pub fn set_target(&mut self, target: T) {
+        if target == self.target {
+            return;
+        }
+        self.target = target;
+        if self.ramp_samples == 0 {
+            self.value = target;
+            self.steps_left = 0;
+        } else {
+            self.step = (target - self.value) / T::from_f64(self.ramp_samples as f64);
+            self.steps_left = self.ramp_samples;
+        }
+    }
+
+    //Jumps straight to the target
+    pub fn reset(&mut self) {
+        self.value = self.target;
+        self.steps_left = 0;
+    }
+
+    pub fn next(&mut self) -> T {
+        if self.steps_left > 0 {
+            self.steps_left -= 1;
+            self.value = if self.steps_left == 0 {
+                self.target
+            } else {
+                self.value + self.step
+            };
+        }
+        self.value
+    }
+}
@@ -0,0 +1,281 @@
+use crate::parameter::Smoother;
+use crate::units::{db_to_lin, Float};
+
+//A mono building block, signal paths are assembled by chaining these together
+pub trait Processor<T: Float> {
+    //Clears the state, as if it had only ever heard silence
+    fn reset(&mut self);
+
+    fn set_sample_rate(&mut self, sample_rate: f32);
+
+    fn process(&mut self, x: T) -> T;
+
+    fn process_block(&mut self, buffer: &mut [T]) {
+        for x in buffer.iter_mut() {
+            *x = self.process(*x);
+        }
+    }
+
+    //Delay added to the signal, in samples
+    fn latency(&self) -> usize {
+        0
+    }
+
+    //Feeds this processor's output into next
+    fn then<P: Processor<T>>(self, next: P) -> Chain<Self, P>
+    where
+        Self: Sized,
+    {
+        Chain {
+            first: self,
+            second: next,
+        }
+    }
+}
+
+//Two processors in series, longer paths nest chains: a.then(b).then(c).
+//The nodes stay public so their settings can still be changed once assembled.
+pub struct Chain<A, B> {
+    pub first: A,
+    pub second: B,
+}
+
+impl<T: Float, A: Processor<T>, B: Processor<T>> Processor<T> for Chain<A, B> {
+    fn reset(&mut self) {
+        self.first.reset();
+        self.second.reset();
+    }
+
+    fn set_sample_rate(&mut self, sample_rate: f32) {
+        self.first.set_sample_rate(sample_rate);
+        self.second.set_sample_rate(sample_rate);
+    }
+
+    fn process(&mut self, x: T) -> T {
+        self.second.process(self.first.process(x))
+    }
+
+    //Each node runs over the whole block before the next one
+    fn process_block(&mut self, buffer: &mut [T]) {
+        self.first.process_block(buffer);
+        self.second.process_block(buffer);
+    }
+
+    fn latency(&self) -> usize {
+        self.first.latency() + self.second.latency()
+    }
+}
+
+//Passes the signal straight through while bypassed, the processor is reset on the way in
+pub struct Bypass<P> {
+    pub processor: P,
+    bypassed: bool,
+}
+
+impl<P> Bypass<P> {
+    pub fn new(processor: P, bypassed: bool) -> Bypass<P> {
+        Bypass {
+            processor,
+            bypassed,
+        }
+    }
+
+    pub fn bypassed(&self) -> bool {
+        self.bypassed
+    }
+
+    pub fn set_bypassed<T: Float>(&mut self, bypassed: bool)
+    where
+        P: Processor<T>,
+    {
+        if bypassed != self.bypassed {
+            self.bypassed = bypassed;
+            self.processor.reset();
+        }
+    }
+}
+
+impl<T: Float, P: Processor<T>> Processor<T> for Bypass<P> {
+    fn reset(&mut self) {
+        self.processor.reset();
+    }
+
+    fn set_sample_rate(&mut self, sample_rate: f32) {
+        self.processor.set_sample_rate(sample_rate);
+    }
+
+    fn process(&mut self, x: T) -> T {
+        if self.bypassed {
+            x
+        } else {
+            self.processor.process(x)
+        }
+    }
+
+    fn process_block(&mut self, buffer: &mut [T]) {
+        if !self.bypassed {
+            self.processor.process_block(buffer);
+        }
+    }
+
+    fn latency(&self) -> usize {
+        if self.bypassed {
+            0
+        } else {
+            self.processor.latency()
+        }
+    }
+}
+
+//Multiplies by a gain that ramps to each new setting instead of jumping
+pub struct Gain<T: Float> {
+    smoother: Smoother<T>,
+    //Ramp time, ms
+    smoothing: f32,
+    sample_rate: f32,
+}
+
+impl<T: Float> Default for Gain<T> {
+    fn default() -> Gain<T> {
+        Gain::new()
+    }
+}
+
+impl<T: Float> Gain<T> {
+    //Starts at unity
+    pub fn new() -> Gain<T> {
+        Gain {
+            smoother: Smoother::new(T::from_f64(1.0)),
+            smoothing: 0.0,
+            sample_rate: 44100.0,
+        }
+    }
+
+    //Ramp time in ms for gain changes
+    pub fn set_smoothing(&mut self, smoothing: f32) {
+        if smoothing != self.smoothing {
+            self.smoothing = smoothing;
+            self.smoother.set_ramp(smoothing, self.sample_rate);
+        }
+    }
+
+    //gain units are dB
+    pub fn set_gain(&mut self, gain: f32) {
+        self.smoother.set_target(db_to_lin(T::from_f32(gain)));
+    }
+}
+
+impl<T: Float> Processor<T> for Gain<T> {
+    //Skips any ramp in progress
+    fn reset(&mut self) {
+        self.smoother.reset();
+    }
+
+    fn set_sample_rate(&mut self, sample_rate: f32) {
+        self.sample_rate = sample_rate;
+        self.smoother.set_ramp(self.smoothing, sample_rate);
+    }
+
+    fn process(&mut self, x: T) -> T {
+        x * self.smoother.next()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    //A whole number of samples of delay, counting its resets
+    struct Delay {
+        buffer: Vec<f32>,
+        position: usize,
+        resets: usize,
+    }
+
+    impl Delay {
+        fn new(latency: usize) -> Delay {
+            Delay {
+                buffer: vec![0.0; latency],
+                position: 0,
+                resets: 0,
+            }
+        }
+    }
+
+    impl Processor<f32> for Delay {
+        fn reset(&mut self) {
+            self.buffer.iter_mut().for_each(|x| *x = 0.0);
+            self.resets += 1;
+        }
+
+        fn set_sample_rate(&mut self, _: f32) {}
+
+        fn process(&mut self, x: f32) -> f32 {
+            if self.buffer.is_empty() {
+                return x;
+            }
+            let y = std::mem::replace(&mut self.buffer[self.position], x);
+            self.position = (self.position + 1) % self.buffer.len();
+            y
+        }
+
+        fn latency(&self) -> usize {
+            self.buffer.len()
+        }
+    }
+
+    fn impulse(length: usize) -> Vec<f32> {
+        (0..length)
+            .map(|i| if i == 0 { 1.0 } else { 0.0 })
+            .collect()
+    }
+
+    #[test]
+    fn chain_latency_is_the_sum() {
+        let mut chain = Delay::new(3).then(Delay::new(5)).then(Delay::new(0));
+        assert_eq!(chain.latency(), 8);
+        let mut buffer = impulse(16);
+        chain.process_block(&mut buffer);
+        assert_eq!(buffer.iter().position(|x| *x == 1.0), Some(chain.latency()));
+    }
+
+    #[test]
+    fn chain_resets_every_node() {
+        let mut chain = Delay::new(3).then(Delay::new(5)).then(Delay::new(2));
+        let mut buffer = vec![1.0; 4];
+        chain.process_block(&mut buffer);
+        chain.reset();
+        assert_eq!(chain.first.first.resets, 1);
+        assert_eq!(chain.first.second.resets, 1);
+        assert_eq!(chain.second.resets, 1);
+        //Nothing from before the reset comes out
+        let mut buffer = vec![0.0; 16];
+        chain.process_block(&mut buffer);
+        assert!(buffer.iter().all(|x| *x == 0.0));
+    }
+
+    #[test]
+    fn bypass_passes_audio_through() {
+        let input: Vec<f32> = (0..64).map(|i| (i as f32 * 0.3).sin()).collect();
+        let mut bypass = Bypass::new(Delay::new(4).then(Gain::new()), true);
+        bypass.processor.second.set_gain(-6.0);
+        assert_eq!(bypass.latency(), 0);
+        let mut buffer = input.clone();
+        bypass.process_block(&mut buffer);
+        assert_eq!(buffer, input);
+        let samples: Vec<f32> = input.iter().map(|x| bypass.process(*x)).collect();
+        assert_eq!(samples, input);
+
+        //Switching resets the processor, so it starts from silence
+        bypass.set_bypassed(false);
+        assert_eq!(bypass.processor.first.resets, 1);
+        assert_eq!(bypass.latency(), 4);
+        let mut buffer = input.clone();
+        bypass.process_block(&mut buffer);
+        let gain = db_to_lin(-6.0);
+        assert!(buffer[..4].iter().all(|x| *x == 0.0));
+        for (y, x) in buffer[4..].iter().zip(input.iter()) {
+            assert_eq!(*y, *x * gain);
+        }
+    }
+}
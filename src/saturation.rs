@@ -0,0 +1,160 @@
+use crate::oversampling::{Downsampler, Upsampler, FILTER_LENGTH};
+use crate::parameter::Smoother;
+use crate::processor::Processor;
+use crate::units::{db_to_lin, Float};
+
+//The curves add harmonics well above the base rate nyquist, they run at 4x to keep
+//the aliasing down
+const FACTOR: usize = 4;
+
+//Delay added to the audio, in samples
+pub const SATURATION_LATENCY: usize = FILTER_LENGTH;
+
+//Where the tube curve's operating point sits, more bias is more asymmetric
+const TUBE_BIAS: f64 = 0.3;
+
+#[derive(Clone, Copy, PartialEq)]
+pub enum Curve {
+    Off,
+    Tanh,
+    //Cubic soft clip, reaches +-1.0 at +-1.5 and hard limits from there
+    Polynomial,
+    //Biased tanh, clips the positive half earlier for even harmonics
+    Tube,
+}
+
+impl Curve {
+    pub fn from_param(x: f32) -> Curve {
+        match x.round() as i32 {
+            0 => Curve::Off,
+            1 => Curve::Tanh,
+            2 => Curve::Polynomial,
+            _ => Curve::Tube,
+        }
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Curve::Off => "Off",
+            Curve::Tanh => "Tanh",
+            Curve::Polynomial => "Polynomial",
+            Curve::Tube => "Tube",
+        }
+    }
+
+    pub fn apply<T: Float>(self, x: T) -> T {
+        match self {
+            Curve::Off => x,
+            Curve::Tanh => x.tanh(),
+            Curve::Polynomial => {
+                let limit = T::from_f64(1.5);
+                let x = x.max(-limit).min(limit);
+                x - T::from_f64(4.0 / 27.0) * x.powi(3)
+            }
+            Curve::Tube => {
+                //Scaled so the slope at 0 stays 1.0
+                let bias = T::from_f64(TUBE_BIAS);
+                let slope = T::from_f64(1.0 - TUBE_BIAS.tanh().powi(2));
+                ((x + bias).tanh() - bias.tanh()) / slope
+            }
+        }
+    }
+}
+
+pub struct Saturator<T: Float> {
+    curve: Curve,
+    drive: Smoother<T>,
+    sample_rate: f32,
+    upsampler: Upsampler<T>,
+    downsampler: Downsampler<T>,
+    buffer: [T; FACTOR],
+    //One pole 5hz high pass, removes the offset the asymmetric curve adds
+    dc_coeff: T,
+    dc_x1: T,
+    dc_y1: T,
+}
+
+impl<T: Float> Saturator<T> {
+    pub fn new(sample_rate: f32) -> Saturator<T> {
+        let mut saturator = Saturator {
+            curve: Curve::Off,
+            drive: Smoother::new(T::from_f64(1.0)),
+            sample_rate,
+            upsampler: Upsampler::new(FACTOR),
+            downsampler: Downsampler::new(FACTOR),
+            buffer: [T::default(); FACTOR],
+            dc_coeff: T::default(),
+            dc_x1: T::default(),
+            dc_y1: T::default(),
+        };
+        saturator.set_sample_rate(sample_rate);
+        saturator
+    }
+
+    //drive units are dB
+    pub fn update(&mut self, curve: Curve, drive: f32) {
+        if curve != self.curve {
+            self.curve = curve;
+            self.reset();
+        }
+        self.drive.set_target(db_to_lin(T::from_f32(drive)));
+    }
+
+    //Ramp time in ms for drive changes
+    pub fn set_smoothing(&mut self, smoothing: f32) {
+        self.drive.set_ramp(smoothing, self.sample_rate);
+    }
+
+    pub fn set_sample_rate(&mut self, sample_rate: f32) {
+        self.sample_rate = sample_rate;
+        self.dc_coeff =
+            (-T::from_f64(2.0) * T::PI * T::from_f64(5.0) / T::from_f32(sample_rate)).exp();
+    }
+
+    pub fn reset(&mut self) {
+        self.upsampler.reset();
+        self.downsampler.reset();
+        self.dc_x1 = T::default();
+        self.dc_y1 = T::default();
+    }
+
+    pub fn process(&mut self, x: T) -> T {
+        self.upsampler.process(x, &mut self.buffer);
+        let drive = self.drive.next();
+        for y in self.buffer.iter_mut() {
+            *y = self.curve.apply(*y * drive);
+        }
+        let y = self.downsampler.process(&self.buffer);
+        if self.curve == Curve::Tube {
+            self.dc_y1 = y - self.dc_x1 + self.dc_coeff * self.dc_y1;
+            self.dc_x1 = y;
+            self.dc_y1
+        } else {
+            y
+        }
+    }
+
+    pub fn process_block(&mut self, buffer: &mut [T]) {
+        for x in buffer.iter_mut() {
+            *x = self.process(*x);
+        }
+    }
+}
+
+impl<T: Float> Processor<T> for Saturator<T> {
+    fn reset(&mut self) {
+        Saturator::reset(self);
+    }
+
+    fn set_sample_rate(&mut self, sample_rate: f32) {
+        Saturator::set_sample_rate(self, sample_rate);
+    }
+
+    fn process(&mut self, x: T) -> T {
+        Saturator::process(self, x)
+    }
+
+    fn latency(&self) -> usize {
+        SATURATION_LATENCY
+    }
+}
@@ -0,0 +1,128 @@
+use crate::low_pass_filter::{BellFilter, HighPassFilter, LowPassFilter, BUTTERWORTH_SHARP};
+use crate::processor::Processor;
+use crate::units::Float;
+
+pub const SC_FREQ_MIN: f32 = 20.0;
+pub const SC_FREQ_MAX: f32 = 20000.0;
+
+//Shapes what the detector hears, the audio path is never filtered.
+//The high & low pass are bypassed when set to the ends of their range,
+//the bell is bypassed at 0dB gain.
+pub struct SidechainFilter<T: Float> {
+    high_pass: HighPassFilter<T>,
+    low_pass: LowPassFilter<T>,
+    bell: BellFilter<T>,
+    hp_freq: f32,
+    lp_freq: f32,
+    bell_freq: f32,
+    bell_q: f32,
+    bell_gain: f32,
+    sample_rate: f32,
+}
+
+impl<T: Float> SidechainFilter<T> {
+    pub fn new(sample_rate: f32) -> SidechainFilter<T> {
+        let mut filter = SidechainFilter {
+            high_pass: HighPassFilter::new(SC_FREQ_MIN, BUTTERWORTH_SHARP, sample_rate),
+            low_pass: LowPassFilter::new(SC_FREQ_MAX, BUTTERWORTH_SHARP, sample_rate),
+            bell: BellFilter::new(1000.0, 1.0, 0.0, sample_rate),
+            hp_freq: SC_FREQ_MIN,
+            lp_freq: SC_FREQ_MAX,
+            bell_freq: 1000.0,
+            bell_q: 1.0,
+            bell_gain: 0.0,
+            sample_rate,
+        };
+        filter.update_filters();
+        filter
+    }
+
+    pub fn set_sample_rate(&mut self, sample_rate: f32) {
+        self.sample_rate = sample_rate;
+        self.high_pass.set_sample_rate(sample_rate);
+        self.low_pass.set_sample_rate(sample_rate);
+        self.bell.set_sample_rate(sample_rate);
+        self.update_filters();
+    }
+
+    pub fn update(
+        &mut self,
+        hp_freq: f32,
+        lp_freq: f32,
+        bell_freq: f32,
+        bell_q: f32,
+        bell_gain: f32,
+    ) {
+        if hp_freq != self.hp_freq
+            || lp_freq != self.lp_freq
+            || bell_freq != self.bell_freq
+            || bell_q != self.bell_q
+            || bell_gain != self.bell_gain
+        {
+            self.hp_freq = hp_freq;
+            self.lp_freq = lp_freq;
+            self.bell_freq = bell_freq;
+            self.bell_q = bell_q;
+            self.bell_gain = bell_gain;
+            self.update_filters();
+        }
+    }
+
+    fn update_filters(&mut self) {
+        let max_freq = self.sample_rate * 0.49;
+        self.high_pass.set_freq(self.hp_freq.min(max_freq));
+        self.low_pass.set_freq(self.lp_freq.min(max_freq));
+        self.bell
+            .set(self.bell_freq.min(max_freq), self.bell_q, self.bell_gain);
+    }
+
+    pub fn process(&mut self, x: T) -> T {
+        let mut y = x;
+        if self.hp_freq > SC_FREQ_MIN {
+            y = self.high_pass.process(y);
+        }
+        if self.lp_freq < SC_FREQ_MAX {
+            y = self.low_pass.process(y);
+        }
+        if self.bell_gain != 0.0 {
+            y = self.bell.process(y);
+        }
+        y
+    }
+
+    pub fn reset(&mut self) {
+        self.high_pass.reset();
+        self.low_pass.reset();
+        self.bell.reset();
+    }
+
+    pub fn process_block(&mut self, buffer: &mut [T]) {
+        if self.hp_freq > SC_FREQ_MIN {
+            self.high_pass.process_block(buffer);
+        }
+        if self.lp_freq < SC_FREQ_MAX {
+            self.low_pass.process_block(buffer);
+        }
+        if self.bell_gain != 0.0 {
+            self.bell.process_block(buffer);
+        }
+    }
+}
+
+impl<T: Float> Processor<T> for SidechainFilter<T> {
+    fn reset(&mut self) {
+        SidechainFilter::reset(self);
+    }
+
+    fn set_sample_rate(&mut self, sample_rate: f32) {
+        SidechainFilter::set_sample_rate(self, sample_rate);
+    }
+
+    fn process(&mut self, x: T) -> T {
+        SidechainFilter::process(self, x)
+    }
+
+    fn process_block(&mut self, buffer: &mut [T]) {
+        SidechainFilter::process_block(self, buffer);
+    }
+}
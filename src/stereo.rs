@@ -0,0 +1,248 @@
+use crate::compressor::{Compressor, KneeShape, Mode};
+use crate::processor::Processor;
+use crate::transfer_curve::TransferCurve;
+use crate::units::Float;
+
+//How the two detector channels are derived from the stereo key signal
+#[derive(Clone, Copy, PartialEq)]
+pub enum StereoLink {
+    //Both channels use max(|L|, |R|)
+    Max,
+    //Both channels use |L + R| / 2, anti-phase content cancels out
+    Sum,
+    //Each channel only hears itself
+    DualMono,
+    //Crossfade between DualMono (0%) and Max (100%) using the link parameter
+    Blend,
+}
+
+impl StereoLink {
+    pub fn from_param(x: f32) -> StereoLink {
+        match x.round() as i32 {
+            0 => StereoLink::Max,
+            1 => StereoLink::Sum,
+            2 => StereoLink::DualMono,
+            _ => StereoLink::Blend,
+        }
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            StereoLink::Max => "Max",
+            StereoLink::Sum => "Sum",
+            StereoLink::DualMono => "Dual Mono",
+            StereoLink::Blend => "Blend",
+        }
+    }
+}
+
+//link is 0.0 - 1.0 and only used by StereoLink::Blend
+//Returns the left & right detector inputs
+pub fn detector_inputs<T: Float>(mode: StereoLink, link: f32, key_l: T, key_r: T) -> (T, T) {
+    let (abs_l, abs_r) = (key_l.abs(), key_r.abs());
+    match mode {
+        StereoLink::Max => {
+            let max = abs_l.max(abs_r);
+            (max, max)
+        }
+        StereoLink::Sum => {
+            let sum = (key_l + key_r).abs() * T::from_f64(0.5);
+            (sum, sum)
+        }
+        StereoLink::DualMono => (abs_l, abs_r),
+        StereoLink::Blend => {
+            let max = abs_l.max(abs_r);
+            let link = T::from_f32(link);
+            (abs_l + link * (max - abs_l), abs_r + link * (max - abs_r))
+        }
+    }
+}
+
+pub fn encode_mid_side<T: Float>(left: T, right: T) -> (T, T) {
+    let half = T::from_f64(0.5);
+    ((left + right) * half, (left - right) * half)
+}
+
+pub fn decode_mid_side<T: Float>(mid: T, side: T) -> (T, T) {
+    (mid + side, mid - side)
+}
+
+//The left & right compressors plus the routing around them.
+//In M/S mode compressor_l carries the mid channel and compressor_r the side.
+//The setters here are for each compressor's gain computer, its `second` node.
+pub struct StereoCompressor<T: Float> {
+    pub compressor_l: Compressor<T>,
+    pub compressor_r: Compressor<T>,
+    pub mid_side: bool,
+    pub stereo_link: StereoLink,
+    pub link: f32,
+    //Added to the side channel's threshold & ratio in M/S mode
+    pub side_threshold: f32,
+    pub side_ratio: f32,
+}
+
+impl<T: Float> Default for StereoCompressor<T> {
+    fn default() -> StereoCompressor<T> {
+        StereoCompressor::new()
+    }
+}
+
+impl<T: Float> StereoCompressor<T> {
+    pub fn new() -> StereoCompressor<T> {
+        StereoCompressor {
+            compressor_l: Compressor::new(),
+            compressor_r: Compressor::new(),
+            mid_side: false,
+            stereo_link: StereoLink::Sum,
+            link: 1.0,
+            side_threshold: 0.0,
+            side_ratio: 0.0,
+        }
+    }
+
+    pub fn set_feedback(&mut self, feedback: f32) {
+        self.compressor_l.second.set_feedback(feedback);
+        self.compressor_r.second.set_feedback(feedback);
+    }
+
+    pub fn set_smoothing(&mut self, smoothing: f32) {
+        self.compressor_l.second.set_smoothing(smoothing);
+        self.compressor_r.second.set_smoothing(smoothing);
+    }
+
+    pub fn set_mode(&mut self, mode: Mode, range: f32, hysteresis: f32, noise_floor: f32) {
+        self.compressor_l
+            .second
+            .set_mode(mode, range, hysteresis, noise_floor);
+        self.compressor_r
+            .second
+            .set_mode(mode, range, hysteresis, noise_floor);
+    }
+
+    pub fn set_knee_shape(&mut self, shape: KneeShape) {
+        self.compressor_l.second.set_knee_shape(shape);
+        self.compressor_r.second.set_knee_shape(shape);
+    }
+
+    pub fn set_transfer_curve(&mut self, curve: TransferCurve) {
+        self.compressor_l.second.set_transfer_curve(curve);
+        self.compressor_r.second.set_transfer_curve(curve);
+    }
+
+    pub fn set_hold(&mut self, hold: f32) {
+        self.compressor_l.second.set_hold(hold);
+        self.compressor_r.second.set_hold(hold);
+    }
+
+    pub fn set_auto_release_shape(&mut self, shape: f32) {
+        self.compressor_l.second.set_auto_release_shape(shape);
+        self.compressor_r.second.set_auto_release_shape(shape);
+    }
+
+    //Same arguments as Compressor::update_prams
+    #[allow(clippy::too_many_arguments)]
+    pub fn update_prams(
+        &mut self,
+        threshold: f32,
+        knee: f32,
+        pre_smooth: f32,
+        rms_size: f32,
+        ratio: f32,
+        attack: f32,
+        release: f32,
+        gain: f32,
+        sample_rate: f32,
+    ) {
+        let (side_threshold, side_ratio) = if self.mid_side {
+            (self.side_threshold, self.side_ratio)
+        } else {
+            (0.0, 0.0)
+        };
+        self.compressor_l.update_prams(
+            threshold,
+            knee,
+            pre_smooth,
+            rms_size,
+            ratio,
+            attack,
+            release,
+            gain,
+            sample_rate,
+        );
+        self.compressor_r.update_prams(
+            threshold + side_threshold,
+            knee,
+            pre_smooth,
+            rms_size,
+            (ratio + side_ratio).max(1.0),
+            attack,
+            release,
+            gain,
+            sample_rate,
+        );
+    }
+
+    fn key_levels(&self, key: (T, T)) -> (T, T) {
+        if self.mid_side {
+            let (key_m, key_s) = encode_mid_side(key.0, key.1);
+            (key_m.abs(), key_s.abs())
+        } else {
+            detector_inputs(self.stereo_link, self.link, key.0, key.1)
+        }
+    }
+
+    fn apply(&self, audio: (T, T), cv: (T, T)) -> (T, T) {
+        if self.mid_side {
+            let (mid, side) = encode_mid_side(audio.0, audio.1);
+            decode_mid_side(mid * cv.0, side * cv.1)
+        } else {
+            (audio.0 * cv.0, audio.1 * cv.1)
+        }
+    }
+
+    //Returns the compressed left & right audio and the left & right (or mid & side) attenuation
+    pub fn process(&mut self, audio: (T, T), key: (T, T)) -> ((T, T), (T, T)) {
+        let (detector_l, detector_r) = self.key_levels(key);
+
+        let cv_l = self.compressor_l.process(detector_l);
+        let cv_r = self.compressor_r.process(detector_r);
+
+        (self.apply(audio, (cv_l, cv_r)), (cv_l, cv_r))
+    }
+
+    //Block version of process, compresses audio in place and writes the attenuation to cv
+    pub fn process_block(
+        &mut self,
+        audio: (&mut [T], &mut [T]),
+        key: (&[T], &[T]),
+        cv: (&mut [T], &mut [T]),
+    ) {
+        let (audio_l, audio_r) = audio;
+        let (cv_l, cv_r) = cv;
+        for (((key_l, key_r), cv_l), cv_r) in key
+            .0
+            .iter()
+            .zip(key.1.iter())
+            .zip(cv_l.iter_mut())
+            .zip(cv_r.iter_mut())
+        {
+            let (detector_l, detector_r) = self.key_levels((*key_l, *key_r));
+            *cv_l = detector_l;
+            *cv_r = detector_r;
+        }
+
+        self.compressor_l.process_block(cv_l);
+        self.compressor_r.process_block(cv_r);
+
+        for (((audio_l, audio_r), cv_l), cv_r) in audio_l
+            .iter_mut()
+            .zip(audio_r.iter_mut())
+            .zip(cv_l.iter())
+            .zip(cv_r.iter())
+        {
+            let (out_l, out_r) = self.apply((*audio_l, *audio_r), (*cv_l, *cv_r));
+            *audio_l = out_l;
+            *audio_r = out_r;
+        }
+    }
+}
@@ -0,0 +1,178 @@
+use crate::units::Float;
+use std::cmp::Ordering;
+
+//Breakpoints a custom curve is drawn with
+pub const MAX_POINTS: usize = 6;
+
+//Points closer together than this in input are merged, dB
+const MIN_SPACING: f32 = 0.1;
+
+//Most the curve boosts by, dB, so a point dragged into a corner can't pull the noise floor up
+//by the whole range. The same default as the Upward mode's Range.
+pub const MAX_GAIN: f32 = 40.0;
+
+//A static curve through input dB -> output dB breakpoints, for multi-slope shapes no single
+//ratio can express. Between points it's a monotone cubic spline (Fritsch-Carlson), so it
+//never overshoots a point or falls as the input rises. Below the first point the curve
+//continues at unity slope, above the last it continues at the last point's slope. The output
+//is never more than MAX_GAIN above the input.
+#[derive(Clone, Copy, PartialEq)]
+pub struct TransferCurve {
+    input: [f32; MAX_POINTS],
+    output: [f32; MAX_POINTS],
+    //Slope at each point
+    tangent: [f32; MAX_POINTS],
+    len: usize,
+}
+
+impl TransferCurve {
+    //points are (input, output) in dB and can be in any order. Outputs below a previous
+    //point's are raised to it.
+    pub fn new(points: &[(f32, f32)]) -> TransferCurve {
+        let mut sorted = [(0.0, 0.0); MAX_POINTS];
+        let count = points.len().min(MAX_POINTS);
+        sorted[..count].copy_from_slice(&points[..count]);
+        sorted[..count].sort_by(|a, b| a.0.partial_cmp(&b.0).unwrap_or(Ordering::Equal));
+
+        let mut curve = TransferCurve {
+            input: [0.0; MAX_POINTS],
+            output: [0.0; MAX_POINTS],
+            tangent: [1.0; MAX_POINTS],
+            len: 0,
+        };
+        for &(input, output) in sorted[..count].iter() {
+            let mut output = output;
+            if curve.len > 0 {
+                let last = curve.len - 1;
+                if input - curve.input[last] < MIN_SPACING {
+                    continue;
+                }
+                output = output.max(curve.output[last]);
+            }
+            curve.input[curve.len] = input;
+            curve.output[curve.len] = output;
+            curve.len += 1;
+        }
+        curve.set_tangents();
+        curve
+    }
+
+    fn secant(&self, i: usize) -> f32 {
+        (self.output[i + 1] - self.output[i]) / (self.input[i + 1] - self.input[i])
+    }
+
+    fn set_tangents(&mut self) {
+        if self.len < 2 {
+            return;
+        }
+        let last = self.len - 1;
+        self.tangent[0] = self.secant(0);
+        self.tangent[last] = self.secant(last - 1);
+        for i in 1..last {
+            self.tangent[i] = (self.secant(i - 1) + self.secant(i)) * 0.5;
+        }
+        //Limits the tangents of each segment so the cubic stays monotone
+        for i in 0..last {
+            let secant = self.secant(i);
+            if secant <= 0.0 {
+                self.tangent[i] = 0.0;
+                self.tangent[i + 1] = 0.0;
+                continue;
+            }
+            let a = self.tangent[i] / secant;
+            let b = self.tangent[i + 1] / secant;
+            let length = a * a + b * b;
+            if length > 9.0 {
+                let scale = 3.0 / length.sqrt();
+                self.tangent[i] = scale * a * secant;
+                self.tangent[i + 1] = scale * b * secant;
+            }
+        }
+    }
+
+    //Same as compressor::curve_makeup: half the reduction at 0dBFS, none if the curve boosts
+    pub fn makeup(&self) -> f32 {
+        (0.5 * -self.output(0.0f32)).max(0.0)
+    }
+
+    //x units are dB, returns the output level in dB
+    pub fn output<T: Float>(&self, x: T) -> T {
+        //Both sides rise with x, so the smaller one does too and the curve stays monotone
+        self.spline(x).min(x + T::from_f32(MAX_GAIN))
+    }
+
+    fn spline<T: Float>(&self, x: T) -> T {
+        if self.len == 0 {
+            return x;
+        }
+        let first_input = T::from_f32(self.input[0]);
+        if x <= first_input {
+            return T::from_f32(self.output[0]) + x - first_input;
+        }
+        let last = self.len - 1;
+        let last_input = T::from_f32(self.input[last]);
+        if x >= last_input {
+            return T::from_f32(self.output[last])
+                + T::from_f32(self.tangent[last]) * (x - last_input);
+        }
+
+        let mut i = 0;
+        while x >= T::from_f32(self.input[i + 1]) {
+            i += 1;
+        }
+        //Cubic Hermite between points i & i + 1
+        let one = T::from_f64(1.0);
+        let two = T::from_f64(2.0);
+        let three = T::from_f64(3.0);
+        let x0 = T::from_f32(self.input[i]);
+        let width = T::from_f32(self.input[i + 1]) - x0;
+        let t = (x - x0) / width;
+        let t2 = t * t;
+        let t3 = t2 * t;
+        (two * t3 - three * t2 + one) * T::from_f32(self.output[i])
+            + (t3 - two * t2 + t) * width * T::from_f32(self.tangent[i])
+            + (three * t2 - two * t3) * T::from_f32(self.output[i + 1])
+            + (t3 - t2) * width * T::from_f32(self.tangent[i + 1])
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    //Input sweep, dB
+    const SWEEP_START: f32 = -120.0;
+    const SWEEP_END: f32 = 24.0;
+    const SWEEP_STEPS: usize = 1440;
+
+    fn assert_bounded(points: &[(f32, f32)]) {
+        let curve = TransferCurve::new(points);
+        for i in 0..=SWEEP_STEPS {
+            let x = SWEEP_START + (SWEEP_END - SWEEP_START) * i as f32 / SWEEP_STEPS as f32;
+            let gain = curve.output(x) - x;
+            assert!(
+                gain <= MAX_GAIN + 1e-3,
+                "{:?} boosts {}dB by {}dB",
+                points,
+                x,
+                gain
+            );
+        }
+    }
+
+    #[test]
+    fn gain_is_bounded() {
+        //A point dragged into the top left corner
+        assert_bounded(&[(-80.0, 12.0), (-40.0, -30.0), (0.0, -20.0)]);
+        assert_bounded(&[(-80.0, 12.0)]);
+        assert_bounded(&[(-80.0, -80.0), (-79.0, 12.0), (12.0, 12.0)]);
+        assert_bounded(&[
+            (-60.0, 0.0),
+            (-50.0, 0.0),
+            (-40.0, 0.0),
+            (-30.0, 0.0),
+            (-20.0, 0.0),
+            (-10.0, 0.0),
+        ]);
+    }
+}
@@ -0,0 +1,66 @@
+use crate::oversampling::{Upsampler, FILTER_LENGTH};
+use crate::processor::Processor;
+use crate::units::Float;
+
+//Oversampling factor ITU-R BS.1770 recommends for inter-sample peak estimation
+const FACTOR: usize = 4;
+
+//Delay of the estimate behind the input, in samples
+pub const TRUE_PEAK_LATENCY: usize = FILTER_LENGTH / 2;
+
+//Estimates the inter-sample (true) peak by 4x oversampling, per ITU-R BS.1770
+pub struct TruePeakDetector<T: Float> {
+    upsampler: Upsampler<T>,
+    buffer: [T; FACTOR],
+}
+
+impl<T: Float> Default for TruePeakDetector<T> {
+    fn default() -> TruePeakDetector<T> {
+        TruePeakDetector::new()
+    }
+}
+
+impl<T: Float> TruePeakDetector<T> {
+    pub fn new() -> TruePeakDetector<T> {
+        TruePeakDetector {
+            upsampler: Upsampler::new(FACTOR),
+            buffer: [T::default(); FACTOR],
+        }
+    }
+
+    pub fn reset(&mut self) {
+        self.upsampler.reset();
+    }
+
+    //Returns the interpolated sample with the largest magnitude. The sign is kept so the
+    //result can still be summed or mid/side encoded like a regular sample.
+    pub fn process(&mut self, x: T) -> T {
+        self.upsampler.process(x, &mut self.buffer);
+        self.buffer.iter().fold(
+            T::default(),
+            |peak, &y| if y.abs() > peak.abs() { y } else { peak },
+        )
+    }
+
+    pub fn process_block(&mut self, buffer: &mut [T]) {
+        for x in buffer.iter_mut() {
+            *x = self.process(*x);
+        }
+    }
+}
+
+impl<T: Float> Processor<T> for TruePeakDetector<T> {
+    fn reset(&mut self) {
+        TruePeakDetector::reset(self);
+    }
+
+    fn set_sample_rate(&mut self, _sample_rate: f32) {}
+
+    fn process(&mut self, x: T) -> T {
+        TruePeakDetector::process(self, x)
+    }
+
+    fn latency(&self) -> usize {
+        TRUE_PEAK_LATENCY
+    }
+}
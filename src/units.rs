@@ -1,11 +1,162 @@
+use crate::processor::Processor;
 use ringbuf::Consumer;
+use std::ops::{Add, AddAssign, Div, DivAssign, Mul, MulAssign, Neg, Sub, SubAssign};
 
-pub fn db_to_lin(decibels: f32) -> f32 {
-    (10.0f32).powf(decibels * 0.05)
+//The sample type the DSP runs in, f32 for process & f64 for process_f64
+pub trait Float:
+    Copy
+    + Default
+    + PartialOrd
+    + Send
+    + 'static
+    + Add<Output = Self>
+    + Sub<Output = Self>
+    + Mul<Output = Self>
+    + Div<Output = Self>
+    + Neg<Output = Self>
+    + AddAssign
+    + SubAssign
+    + MulAssign
+    + DivAssign
+{
+    const PI: Self;
+
+    fn from_f64(x: f64) -> Self;
+    fn from_f32(x: f32) -> Self;
+    fn to_f32(self) -> f32;
+
+    fn abs(self) -> Self;
+    fn max(self, other: Self) -> Self;
+    fn min(self, other: Self) -> Self;
+    fn exp(self) -> Self;
+    fn sqrt(self) -> Self;
+    fn powi(self, n: i32) -> Self;
+    fn powf(self, n: Self) -> Self;
+    fn log10(self) -> Self;
+    fn ln_1p(self) -> Self;
+    fn tan(self) -> Self;
+    fn tanh(self) -> Self;
+    fn sin(self) -> Self;
+    fn cos(self) -> Self;
+    fn is_finite(self) -> bool;
 }
 
-pub fn lin_to_db(gain: f32) -> f32 {
-    gain.max(0.0).log(10.0) * 20.0
+macro_rules! impl_float {
+    ($t:ident) => {
+        impl Float for $t {
+            const PI: $t = std::$t::consts::PI;
+
+            fn from_f64(x: f64) -> $t {
+                x as $t
+            }
+            fn from_f32(x: f32) -> $t {
+                x as $t
+            }
+            fn to_f32(self) -> f32 {
+                self as f32
+            }
+
+            fn abs(self) -> $t {
+                $t::abs(self)
+            }
+            fn max(self, other: $t) -> $t {
+                $t::max(self, other)
+            }
+            fn min(self, other: $t) -> $t {
+                $t::min(self, other)
+            }
+            fn exp(self) -> $t {
+                $t::exp(self)
+            }
+            fn sqrt(self) -> $t {
+                $t::sqrt(self)
+            }
+            fn powi(self, n: i32) -> $t {
+                $t::powi(self, n)
+            }
+            fn powf(self, n: $t) -> $t {
+                $t::powf(self, n)
+            }
+            fn log10(self) -> $t {
+                $t::log10(self)
+            }
+            fn ln_1p(self) -> $t {
+                $t::ln_1p(self)
+            }
+            fn tan(self) -> $t {
+                $t::tan(self)
+            }
+            fn tanh(self) -> $t {
+                $t::tanh(self)
+            }
+            fn sin(self) -> $t {
+                $t::sin(self)
+            }
+            fn cos(self) -> $t {
+                $t::cos(self)
+            }
+            fn is_finite(self) -> bool {
+                $t::is_finite(self)
+            }
+        }
+    };
+}
+
+impl_float!(f32);
+impl_float!(f64);
+
+#[cfg(not(feature = "fast-db"))]
+pub fn db_to_lin<T: Float>(decibels: T) -> T {
+    T::from_f64(10.0).powf(decibels / T::from_f64(20.0))
+}
+
+#[cfg(not(feature = "fast-db"))]
+pub fn lin_to_db<T: Float>(gain: T) -> T {
+    gain.max(T::from_f64(0.0)).log10() * T::from_f64(20.0)
+}
+
+//The fast-db versions are within 0.001dB, and don't call into libm so loops over them
+//can be vectorized. They run in f32 whatever the sample type.
+#[cfg(feature = "fast-db")]
+pub fn db_to_lin<T: Float>(decibels: T) -> T {
+    //log2(10) / 20
+    T::from_f32(fast_exp2(decibels.to_f32() * 0.166_096_4))
+}
+
+#[cfg(feature = "fast-db")]
+pub fn lin_to_db<T: Float>(gain: T) -> T {
+    let gain = gain.max(T::from_f64(0.0)).to_f32();
+    let db = if gain == 0.0 {
+        f32::NEG_INFINITY
+    } else if gain == f32::INFINITY {
+        f32::INFINITY
+    } else {
+        //20 * log10(2)
+        fast_log2(gain) * 6.020_6
+    };
+    T::from_f32(db)
+}
+
+//Exponent plus a polynomial fit of log2 over the mantissa, exact at powers of 2
+#[cfg(feature = "fast-db")]
+fn fast_log2(x: f32) -> f32 {
+    let bits = x.to_bits();
+    let exponent = ((bits >> 23) & 0xFF) as i32 - 127;
+    let t = f32::from_bits((bits & 0x007F_FFFF) | 0x3F80_0000) - 1.0;
+    exponent as f32 + t * (1.438_073_2 + t * (-0.674_766_7 + t * (0.317_000_7 - t * 0.080_307_3)))
+}
+
+//Polynomial fit of 2^x over the fraction, scaled by the integer part's exponent bits
+#[cfg(feature = "fast-db")]
+fn fast_exp2(x: f32) -> f32 {
+    if x.is_nan() {
+        return x;
+    }
+    let x = x.max(-126.0).min(127.0);
+    let floor = x.floor();
+    let t = x - floor;
+    let scale = f32::from_bits(((floor as i32 + 127) as u32) << 23);
+    scale * (1.0 + t * (0.692_995_7 + t * (0.241_565_6 + t * (0.051_752_3 + t * 0.013_686_5))))
 }
 
 pub fn to_range(bottom: f32, top: f32, x: f32) -> f32 {
@@ -24,31 +175,31 @@ pub fn sign(a: f32, b: f32) -> f32 {
     }
 }
 
-pub struct VariableRingBuffer {
-    buffer: Vec<f32>,
+pub struct VariableRingBuffer<T: Float> {
+    buffer: Vec<T>,
     position: usize,
     size: usize,
 }
 
-impl VariableRingBuffer {
-    pub fn new(init_size: usize, max_size: usize) -> VariableRingBuffer {
+impl<T: Float> VariableRingBuffer<T> {
+    pub fn new(init_size: usize, max_size: usize) -> VariableRingBuffer<T> {
         VariableRingBuffer {
-            buffer: vec![0.0; max_size],
+            buffer: vec![T::default(); max_size],
             position: 0,
             size: init_size,
         }
     }
 
-    pub fn push(&mut self, value: f32) {
+    pub fn push(&mut self, value: T) {
         self.buffer[self.position] = value;
         self.position = (self.position + 1) % self.size;
     }
 
-    pub fn oldest(&self) -> f32 {
+    pub fn oldest(&self) -> T {
         self.buffer[self.position]
     }
 
-    pub fn get(&self, index: usize) -> f32 {
+    pub fn get(&self, index: usize) -> T {
         let pos = self.position + index;
         if pos > self.size - 1 {
             self.buffer[pos - self.size]
@@ -65,39 +216,172 @@ impl VariableRingBuffer {
         self.size = new_size.min(self.buffer.len());
         self.position = 0;
         for i in self.buffer.iter_mut() {
-            *i = 0.0;
+            *i = T::default();
+        }
+    }
+}
+//Delays by a whole number of samples, a delay of 0 passes the input straight through
+pub struct DelayLine<T: Float> {
+    buffer: VariableRingBuffer<T>,
+    delay: usize,
+}
+
+impl<T: Float> DelayLine<T> {
+    pub fn new(delay: usize, max_delay: usize) -> DelayLine<T> {
+        let mut delay_line = DelayLine {
+            buffer: VariableRingBuffer::new(0, max_delay),
+            delay: 0,
+        };
+        delay_line.set_delay(delay);
+        delay_line
+    }
+
+    pub fn set_delay(&mut self, delay: usize) {
+        if delay != self.delay {
+            self.buffer.resize(delay);
+            self.delay = self.buffer.size();
+        }
+    }
+
+    pub fn delay(&self) -> usize {
+        self.delay
+    }
+
+    pub fn process(&mut self, value: T) -> T {
+        if self.delay == 0 {
+            return value;
         }
+        let delayed = self.buffer.oldest();
+        self.buffer.push(value);
+        delayed
+    }
+
+    pub fn process_block(&mut self, buffer: &mut [T]) {
+        if self.delay == 0 {
+            return;
+        }
+        for x in buffer.iter_mut() {
+            let delayed = self.buffer.oldest();
+            self.buffer.push(*x);
+            *x = delayed;
+        }
+    }
+}
+
+impl<T: Float> Processor<T> for DelayLine<T> {
+    fn reset(&mut self) {
+        self.buffer.resize(self.delay);
+    }
+
+    fn set_sample_rate(&mut self, _sample_rate: f32) {}
+
+    fn process(&mut self, x: T) -> T {
+        DelayLine::process(self, x)
+    }
+
+    fn process_block(&mut self, buffer: &mut [T]) {
+        DelayLine::process_block(self, buffer);
+    }
+
+    fn latency(&self) -> usize {
+        self.delay
     }
 }
-pub struct AccumulatingRMS {
-    buffer: VariableRingBuffer,
-    rms: f32,
+
+pub struct AccumulatingRMS<T: Float> {
+    buffer: VariableRingBuffer<T>,
+    rms: T,
+    size_ms: f32,
 }
 
-impl AccumulatingRMS {
-    pub fn new(sample_rate: usize, rms_size_ms: f32, rms_max_size_samp: usize) -> AccumulatingRMS {
+impl<T: Float> AccumulatingRMS<T> {
+    pub fn new(
+        sample_rate: usize,
+        rms_size_ms: f32,
+        rms_max_size_samp: usize,
+    ) -> AccumulatingRMS<T> {
         AccumulatingRMS {
             buffer: VariableRingBuffer::new(
                 ((sample_rate as f32) * (rms_size_ms / 1000.0)) as usize,
                 rms_max_size_samp,
             ),
-            rms: 0.0,
+            rms: T::default(),
+            size_ms: rms_size_ms,
         }
     }
     pub fn resize(&mut self, sample_rate: usize, rms_size_ms: f32) {
+        self.size_ms = rms_size_ms;
         let new_size = (((sample_rate as f32) * (rms_size_ms / 1000.0)) as usize).max(1);
         if new_size != self.buffer.size() {
             self.buffer.resize(new_size);
-            self.rms = 0.0;
+            self.rms = T::default();
         }
     }
-    pub fn process(&mut self, value: f32) -> f32 {
+    pub fn process(&mut self, value: T) -> T {
         let new_rms_sample = value.powi(2);
 
         //remove the oldest rms value, add new one
         self.rms += -self.buffer.oldest() + new_rms_sample;
         self.buffer.push(new_rms_sample);
-        (self.rms / self.buffer.size() as f32).sqrt()
+        (self.rms / T::from_f64(self.buffer.size() as f64)).sqrt()
+    }
+
+    //Replaces each sample with the rms up to it. Only the running sum is done sample by
+    //sample, the squaring & square roots get their own loops so they can be vectorized.
+    pub fn process_block(&mut self, buffer: &mut [T]) {
+        for x in buffer.iter_mut() {
+            *x = x.powi(2);
+        }
+        for x in buffer.iter_mut() {
+            self.rms += -self.buffer.oldest() + *x;
+            self.buffer.push(*x);
+            *x = self.rms;
+        }
+        let size = T::from_f64(self.buffer.size() as f64);
+        for x in buffer.iter_mut() {
+            *x = (*x / size).sqrt();
+        }
+    }
+}
+
+impl<T: Float> Processor<T> for AccumulatingRMS<T> {
+    fn reset(&mut self) {
+        self.buffer.resize(self.buffer.size());
+        self.rms = T::default();
+    }
+
+    fn set_sample_rate(&mut self, sample_rate: f32) {
+        self.resize(sample_rate as usize, self.size_ms);
+    }
+
+    fn process(&mut self, x: T) -> T {
+        AccumulatingRMS::process(self, x)
+    }
+
+    fn process_block(&mut self, buffer: &mut [T]) {
+        AccumulatingRMS::process_block(self, buffer);
+    }
+}
+
+//Mean over a sliding window
+pub struct MovingAverage<T: Float> {
+    buffer: VariableRingBuffer<T>,
+    sum: T,
+}
+
+impl<T: Float> MovingAverage<T> {
+    pub fn new(size: usize) -> MovingAverage<T> {
+        MovingAverage {
+            buffer: VariableRingBuffer::new(size, size),
+            sum: T::default(),
+        }
+    }
+
+    pub fn process(&mut self, value: T) -> T {
+        //remove the oldest value, add new one
+        self.sum += -self.buffer.oldest() + value;
+        self.buffer.push(value);
+        self.sum / T::from_f64(self.buffer.size() as f64)
     }
 }
 